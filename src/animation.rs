@@ -0,0 +1,179 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{ BufWriter, Write };
+use std::ops::{ Add, Sub, Mul };
+use std::path::Path;
+
+use crate::raytracer::hittable::*;
+use crate::raytracer::math::vec3f::*;
+
+use crate::camera::{ Camera, Multithread_camera };
+
+#[derive( Clone, Copy, PartialEq )]
+pub enum Interpolation {
+    Linear,
+    // Through every key, the ends' tangents pointing at their neighbours.
+    Catmull_rom,
+}
+
+#[derive( Clone, Copy )]
+pub struct Keyframe {
+    pub frame: f64,
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vfov: f64,
+    pub focus_dist: f64,
+}
+
+pub struct Camera_animation {
+    keyframes: Vec<Keyframe>,
+    interpolation: Interpolation,
+}
+
+impl Camera_animation {
+
+    pub fn new( interpolation: Interpolation ) -> Self {
+        Camera_animation { keyframes: Vec::new(), interpolation }
+    }
+
+    // Keys stay ordered by frame.
+    pub fn add( &mut self, key: Keyframe ) {
+        let at = self.keyframes.partition_point( |k| k.frame <= key.frame );
+        self.keyframes.insert( at, key );
+    }
+
+    // Sets the camera's animated parameters for frame, holding the first and
+    // last keys outside their range. initializer is still up to the caller.
+    pub fn apply( &self, camera: &mut Camera, frame: f64 ) {
+
+        let keys = &self.keyframes;
+        if keys.is_empty() {
+            return
+        }
+
+        let next = keys.partition_point( |k| k.frame <= frame );
+
+        let key =
+            if next == 0 {
+                keys[0]
+            } else if next == keys.len() {
+                keys[keys.len() - 1]
+            } else {
+                let ( k1, k2 ) = ( next - 1, next );
+                let k0         = if k1 > 0 { k1 - 1 } else { k1 };
+                let k3         = usize::min( k2 + 1, keys.len() - 1 );
+
+                let t = ( frame - keys[k1].frame ) / ( keys[k2].frame - keys[k1].frame );
+
+                self.between( &keys[k0], &keys[k1], &keys[k2], &keys[k3], t )
+            };
+
+        camera.lookfrom   = key.lookfrom;
+        camera.lookat     = key.lookat;
+        camera.vfov       = key.vfov;
+        camera.focus_dist = key.focus_dist;
+    }
+
+    // Renders frames first to last inclusive as prefix0001.ppm and so on, or
+    // .pam when the camera writes alpha, making prefix's directory if needed.
+    pub fn render_sequence( &self, camera: &Camera, world: &'static impl Hittable
+                          , first: i32, last: i32, prefix: &str ) -> io::Result<()> {
+
+        for frame in first..=last {
+
+            let mut frame_camera = camera.clone();
+
+            self.apply( &mut frame_camera, frame as f64 );
+            frame_camera.initializer();
+
+            let extension = if frame_camera.has_alpha() { "pam" } else { "ppm" };
+            let path      = format!( "{}{:04}.{}", prefix, frame, extension );
+
+            if let Some( parent ) = Path::new( &path ).parent() {
+                fs::create_dir_all( parent )?;
+            }
+
+            eprintln!( "Frame {} of {} to {}", frame - first + 1, last - first + 1, path );
+
+            let mut output = BufWriter::new( File::create( &path )? );
+            Multithread_camera::new( frame_camera ).render_multithread_to( world, &mut output );
+            output.flush()?;
+        }
+
+        Ok( () )
+    }
+
+    fn between( &self, k0: &Keyframe, k1: &Keyframe, k2: &Keyframe, k3: &Keyframe, t: f64 ) -> Keyframe {
+
+        match self.interpolation {
+
+            Interpolation::Linear => Keyframe {
+                frame: lerp( k1.frame, k2.frame, t ),
+                lookfrom: lerp( k1.lookfrom, k2.lookfrom, t ),
+                lookat: lerp( k1.lookat, k2.lookat, t ),
+                vfov: lerp( k1.vfov, k2.vfov, t ),
+                focus_dist: lerp( k1.focus_dist, k2.focus_dist, t ),
+            },
+
+            Interpolation::Catmull_rom => Keyframe {
+                frame: lerp( k1.frame, k2.frame, t ),
+                lookfrom: catmull_rom( k0.lookfrom, k1.lookfrom, k2.lookfrom, k3.lookfrom, t ),
+                lookat: catmull_rom( k0.lookat, k1.lookat, k2.lookat, k3.lookat, t ),
+                vfov: catmull_rom( k0.vfov, k1.vfov, k2.vfov, k3.vfov, t ),
+                focus_dist: catmull_rom( k0.focus_dist, k1.focus_dist, k2.focus_dist, k3.focus_dist, t ),
+            },
+        }
+    }
+}
+
+fn lerp<T>( a: T, b: T, t: f64 ) -> T
+    where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {
+    a + ( b - a ) * t
+}
+
+// Uniform Catmull-Rom between p1 and p2.
+fn catmull_rom<T>( p0: T, p1: T, p2: T, p3: T, t: f64 ) -> T
+    where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {
+
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    ( p1 * 2.0
+    + ( p2 - p0 ) * t
+    + ( p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3 ) * t2
+    + ( p1 * 3.0 - p0 - p2 * 3.0 + p3 ) * t3 ) * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn catmull_rom_passes_through_the_inner_points() {
+
+        let ( p0, p1, p2, p3 ) = ( Vec3f( 0.0, 1.0, 0.0 ), Vec3f( 1.0, 3.0, -2.0 ), Vec3f( 4.0, 0.0, 1.0 ), Vec3f( 2.0, 2.0, 5.0 ));
+
+        assert!(( catmull_rom( p0, p1, p2, p3, 0.0 ) - p1 ).near_zero() );
+        assert!(( catmull_rom( p0, p1, p2, p3, 1.0 ) - p2 ).near_zero() );
+    }
+
+    #[test]
+    fn catmull_rom_tangents_at_the_endpoints() {
+
+        let ( p0, p1, p2, p3 ) = ( 0.0, 1.0, 4.0, 2.0 );
+        let h                  = 1e-6;
+
+        let start = ( catmull_rom( p0, p1, p2, p3, h ) - p1 ) / h;
+        let end   = ( p2 - catmull_rom( p0, p1, p2, p3, 1.0 - h )) / h;
+
+        assert!(( start - 0.5 * ( p2 - p0 )).abs() < 1e-4 );
+        assert!(( end - 0.5 * ( p3 - p1 )).abs() < 1e-4 );
+    }
+
+    #[test]
+    fn catmull_rom_keeps_even_steps_linear() {
+        assert!(( catmull_rom( 0.0, 1.0, 2.0, 3.0, 0.25 ) - 1.25 ).abs() < 1e-12 );
+    }
+}
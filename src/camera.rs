@@ -1,32 +1,174 @@
 
-use std::io::Write;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{ BufWriter, Write };
 use std::io::stdout;
 
 use std::thread;
+use std::time::Instant;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::atomic::{ AtomicI64, Ordering };
 
 use math::utilities::degrees_to_radians;
 use math::utilities::random_double;
+use math::utilities::power_heuristic;
 
 use crate::raytracer::*;
 use crate::raytracer::ray::*;
 use crate::raytracer::color::*;
 use crate::raytracer::hittable::*;
+use crate::raytracer::light::Light;
+use crate::raytracer::background::{ Background
+                                  , Gradient_background };
+use crate::raytracer::spectrum::{ sample_wavelength
+                                , spectral_to_rgb
+                                , at_wavelength
+                                , illuminant_at_wavelength
+                                , hero_only_weight };
 
 use crate::raytracer::math::vec3f::*;
 use crate::raytracer::math::interval::*;
 
+use crate::raytracer::image::Image;
+use crate::raytracer::filter::{ Filter
+                              , Filter_sampler };
+use crate::raytracer::sampler::{ Sampler
+                               , Independent_sampler
+                               , start_pixel_sample
+                               , end_pixel_samples
+                               , start_dimensions
+                               , start_bounce
+                               , random_2d
+                               , FILM_DIMENSION
+                               , LENS_DIMENSION
+                               , LENS_DIMENSIONS
+                               , WAVELENGTH_DIMENSION };
+
+use crate::lens::Lens_system;
+
+#[derive( Clone, Copy, PartialEq )]
+pub enum Fisheye_mapping {
+    // Image radius proportional to the angle off the axis.
+    Equidistant,
+    // Image radius proportional to the sine of half that angle, keeping areas.
+    Equisolid,
+}
+
+#[derive( Clone, Copy, PartialEq )]
+pub enum Projection {
+    Perspective,
+    // Parallel rays, the view being view_width wide in world units.
+    Orthographic,
+    // Whole sphere, longitude across and latitude down, twice as wide as tall.
+    Equirectangular,
+    // Circle inscribed in the image covering fov degrees, transparent outside.
+    Fisheye { fov: f64, mapping: Fisheye_mapping },
+    // The +x, -x, +y, -y, +z and -z faces of the camera's frame side by side,
+    // oriented like OpenGL's. Six times as wide as tall.
+    Cube_map,
+}
+
+// Shape of the thin lens' aperture. Polygons have their corners on the unit
+// circle, and a mask's brightness tells how open each point of the square
+// around it is.
+#[derive( Clone )]
+pub enum Aperture {
+    Disk,
+    Polygon { blades: u32, rotation: f64 },
+    Mask( Arc<Image> ),
+}
+
+// Where the left and right eye images go, the left one first.
+#[derive( Clone, Copy, PartialEq )]
+pub enum Stereo_layout {
+    Mono,
+    Side_by_side,
+    Top_bottom,
+}
+
+// Running sums of a pixel's filter weighted samples, and Welford's mean and
+// variance of their weighted luminance, uncovered samples counting as black.
 #[derive( Clone, Copy )]
+struct Pixel_estimate {
+    color: Color,
+    covered_weight: f64,
+    total_weight: f64,
+    count: i32,
+    mean: f64,
+    m2: f64,
+}
+
+impl Pixel_estimate {
+
+    fn new() -> Self {
+        Pixel_estimate { color: Color( 0.0, 0.0, 0.0 ), covered_weight: 0.0, total_weight: 0.0, count: 0, mean: 0.0, m2: 0.0 }
+    }
+
+    fn add( &mut self, color: &Color, weight: f64, covered: bool ) {
+
+        self.total_weight += weight;
+
+        if covered {
+            self.color          += weight * *color;
+            self.covered_weight += weight;
+        }
+
+        let y = if covered { weight * luminance( color ) } else { 0.0 };
+
+        self.count += 1;
+
+        let delta  = y - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2   += delta * ( y - self.mean );
+    }
+
+    // Half width of the 95% confidence interval on the luminance, relative to
+    // it, floored at 1/256 so black pixels can settle too.
+    fn relative_error( &self ) -> f64 {
+
+        if self.count < 2 {
+            return f64::INFINITY
+        }
+
+        let variance = self.m2 / ( self.count - 1 ) as f64;
+        let interval = 1.96 * f64::sqrt( variance / self.count as f64 );
+
+        interval / f64::max( f64::abs( self.mean ), 1.0 / 256.0 )
+    }
+}
+
+#[derive( Clone )]
 pub struct Camera {
     
     pub aspect_ratio: f64,
     pub vfov: f64,
 
+    pub projection: Projection,
+    pub view_width: f64,
+
     pub image_width: i32,
     pub samples_per_pixel: i32,
     pub max_depth: i32,
 
+    // With a threshold above zero, pixels stop sampling once past
+    // min_samples_per_pixel and their luminance's 95% confidence interval is
+    // within that fraction of it. samples_per_pixel is then the most they
+    // take, and heatmap_path gets an image of how many each used.
+    pub adaptive_threshold: f64,
+    pub min_samples_per_pixel: i32,
+    pub heatmap_path: Option<String>,
+
+    // Progressive rendering stops after time_budget seconds, after
+    // sample_budget samples over the whole image or once the pixels' mean
+    // relative error is down to noise_target, each off at 0, writing the image
+    // so far every progress_interval seconds.
+    pub time_budget: f64,
+    pub sample_budget: i64,
+    pub noise_target: f64,
+    pub progress_interval: f64,
+
     pub lookfrom: Point3,
     pub lookat: Point3,
     pub vup: Vec3f,
@@ -34,7 +176,41 @@ pub struct Camera {
     pub defocus_angle: f64,
     pub focus_dist: f64,
 
-    pixel_samples_scale: f64,
+    // cat_eye shifts a clipping disk over the aperture towards the image's
+    // edges, by its own radius at the corners when 1.
+    pub aperture: Aperture,
+    pub cat_eye: f64,
+
+    // Tilt in degrees swings the focus plane's bottom towards the camera, about
+    // an axis turned counterclockwise by tilt_direction. Shifts move the view
+    // by fractions of its size without turning the camera.
+    pub tilt: f64,
+    pub tilt_direction: f64,
+    pub shift_x: f64,
+    pub shift_y: f64,
+
+    // A lens system replaces the projection, focused at focus_dist.
+    pub lens: Option<Lens_system>,
+
+    // Eyes are interocular_distance apart along the camera's right axis, their
+    // views meeting at convergence_dist. Equirectangular stereo can instead be
+    // omnidirectional, each direction seen from a circle of eye positions.
+    pub stereo: Stereo_layout,
+    pub interocular_distance: f64,
+    pub convergence_dist: f64,
+    pub omnidirectional_stereo: bool,
+
+    pub lights: Vec<Arc<dyn Light>>,
+    pub background: Arc<dyn Background>,
+
+    pub spectral: bool,
+
+    pub sampler: Arc<dyn Sampler>,
+
+    // Reconstruction filter, radius in pixels.
+    pub filter: Filter,
+
+    filter_sampler: Arc<Filter_sampler>,
     image_height: i32,
 
     center: Point3,
@@ -49,6 +225,8 @@ pub struct Camera {
 
     defocus_disk_u: Vec3f,
     defocus_disk_v: Vec3f,
+
+    focus_normal: Vec3f,
 }
 
 impl Default for Camera {
@@ -58,10 +236,22 @@ impl Default for Camera {
             aspect_ratio: 1.0,
             vfov: 90.0,
 
+            projection: Projection::Perspective,
+            view_width: 2.0,
+
             image_width: 100,
             samples_per_pixel: 10,
             max_depth: 10,
 
+            adaptive_threshold: 0.0,
+            min_samples_per_pixel: 16,
+            heatmap_path: None,
+
+            time_budget: 0.0,
+            sample_budget: 0,
+            noise_target: 0.0,
+            progress_interval: 10.0,
+
             lookfrom: Point3( 0.0, 0.0, 0.0 ),
             lookat: Point3( 0.0, 0.0, -1.0 ),
             vup: Vec3f( 0.0, 1.0, 0.0 ),
@@ -69,7 +259,31 @@ impl Default for Camera {
             defocus_angle: 0.0,
             focus_dist: 10.0,
 
-            pixel_samples_scale: 0.5,
+            aperture: Aperture::Disk,
+            cat_eye: 0.0,
+
+            tilt: 0.0,
+            tilt_direction: 0.0,
+            shift_x: 0.0,
+            shift_y: 0.0,
+
+            lens: None,
+
+            stereo: Stereo_layout::Mono,
+            interocular_distance: 0.064,
+            convergence_dist: 10.0,
+            omnidirectional_stereo: false,
+
+            lights: Vec::new(),
+            background: Arc::new( Gradient_background::default() ),
+
+            spectral: false,
+
+            sampler: Arc::new( Independent_sampler ),
+
+            filter: Filter::Box { radius: 0.5 },
+
+            filter_sampler: Arc::new( Filter_sampler::new( Filter::Box { radius: 0.5 } )),
             image_height: 100,
 
             center: Point3( 0.0, 0.0, 0.0 ),
@@ -84,6 +298,8 @@ impl Default for Camera {
 
             defocus_disk_u: Vec3f( 0.0, 0.0, 0.0 ),
             defocus_disk_v: Vec3f( 0.0, 0.0, 0.0 ),
+
+            focus_normal: Vec3f( 0.0, 0.0, 0.0 ),
         }
     }
 }
@@ -94,27 +310,26 @@ impl Camera {
         
         //self.initializer();
         
-        println!( "P3\n{} {}\n255", self.image_width, self.image_height );
+        let ( width, height ) = self.output_size();
 
-        for j in 0..self.image_height {
+        write_header( &mut stdout(), width, height, self.has_alpha() );
+
+        let mut counts = Vec::new();
+
+        for j in 0..height {
     
-            eprint!( "Scanlines remaining: {}   \r", self.image_height - j );
+            eprint!( "Scanlines remaining: {}   \r", height - j );
             stdout().flush().unwrap();
     
-            for i in 0..self.image_width {
-
-                let mut pixel_color = Color( 0.0, 0.0, 0.0 );
-                
-                for sample in 0..self.samples_per_pixel {
-                    let r = self.get_ray( i, j );
-                    pixel_color += Camera::ray_color( &r, self.max_depth, world );
-                }
-
-                let display_color = self.pixel_samples_scale * pixel_color;
-                write_color( &mut stdout(), &display_color );
+            for i in 0..width {
+                let estimate = self.sample_pixel( i, j, world );
+                self.write_pixel( &mut stdout(), &estimate );
+                counts.push( estimate.count );
             }
         }
 
+        self.write_heatmap( &counts );
+
         eprint!( "\r                                      \r" ); stdout().flush().unwrap();
         eprint!( "Done!\n" );
     }
@@ -123,9 +338,13 @@ impl Camera {
 
         //self.initializer();
         
-        println!( "P3\n{} {}\n255", self.image_width, self.image_height );
+        let ( width, height ) = self.output_size();
+
+        write_header( &mut stdout(), width, height, self.has_alpha() );
+
+        let mut counts = Vec::new();
 
-        let total = self.image_height * self.image_width;
+        let total = height * width;
 
         let ( mut i, mut j ): ( i32, i32 ) = ( 0, -1 );
         for t in 0..total {
@@ -133,47 +352,260 @@ impl Camera {
             eprint!( "Pixels remaining: {}             \r", total - t );
             stdout().flush().unwrap();
 
-            i = t % self.image_width;
+            i = t % width;
             if i == 0 { j += 1; }
 
-            let pixel_color = {
+            let estimate = self.sample_pixel( i, j, world );
+            self.write_pixel( &mut stdout(), &estimate );
+            counts.push( estimate.count );
+        }
+
+        self.write_heatmap( &counts );
+
+        eprint!( "\r                                      \r" ); stdout().flush().unwrap();
+        eprint!( "Done!\n" );
+    }
+
+    // Renders in passes over the whole image, every pixel getting one sample,
+    // then another, then two, four and so on up to 64 at a time, until
+    // samples_per_pixel or a budget runs out. The image goes to path, rewritten
+    // as it refines.
+    pub fn render_progressive( &self, world: &impl Hittable, path: &str ) -> io::Result<()> {
+
+        let ( width, height ) = self.output_size();
 
-                let mut pixel_color= Color( 0.0, 0.0, 0.0 );
+        let number_of_threads = 4;
+
+        let start          = Instant::now();
+        let mut last_write = start;
+        let mut estimates  = vec![ Pixel_estimate::new(); ( width * height ) as usize ];
+        let mut pass_size  = 1;
+        let mut passes     = 0;
+
+        let out_of_time = || self.time_budget > 0.0 && start.elapsed().as_secs_f64() >= self.time_budget;
+
+        // Samples handed out against sample_budget, the ones a pixel didn't use
+        // given back.
+        let taken = AtomicI64::new( 0 );
+        let grant = | want: i32 | -> i32 {
+
+            if self.sample_budget <= 0 {
+                return want
+            }
+
+            let before = taken.fetch_add( want as i64, Ordering::Relaxed );
+            let given  = i64::clamp( self.sample_budget - before, 0, want as i64 );
+
+            taken.fetch_sub( want as i64 - given, Ordering::Relaxed );
+            given as i32
+        };
+
+        loop {
 
-                for sample in 0..self.samples_per_pixel {
-                    let r = self.get_ray( i, j );
-                    pixel_color += Camera::ray_color( &r, self.max_depth, world );
+            let samples_before = estimates.iter().map( |e| e.count as i64 ).sum::<i64>();
+
+            // Rows dealt out to the threads in turn, each checking the clock
+            // and the sample budget before every pixel.
+            thread::scope( |scope| {
+
+                let mut rows_of_thread: Vec<Vec<( i32, &mut [Pixel_estimate] )>> =
+                    ( 0..number_of_threads ).map( |_| Vec::new() ).collect();
+
+                for ( j, row ) in estimates.chunks_mut( width as usize ).enumerate() {
+                    rows_of_thread[j % number_of_threads].push(( j as i32, row ));
                 }
 
-                pixel_color
-            };
+                for rows in rows_of_thread {
+                    let out_of_time = &out_of_time;
+                    let grant       = &grant;
+                    let taken       = &taken;
+                    scope.spawn( move || {
+                        for ( j, row ) in rows {
+                            for ( i, estimate ) in row.iter_mut().enumerate() {
+
+                                if out_of_time() { return }
+
+                                let granted = grant( pass_size );
+                                if granted == 0 { return }
+
+                                let before = estimate.count;
+                                self.add_samples( i as i32, j, world, estimate, granted );
+                                taken.fetch_sub(( granted - ( estimate.count - before )) as i64, Ordering::Relaxed );
+                            }
+                        }
+                    });
+                }
+            });
+
+            passes += 1;
+
+            let samples = estimates.iter().map( |e| e.count as i64 ).sum::<i64>();
+            let noise   =
+                if estimates.iter().any( |e| e.count < 2 && !self.converged( e )) {
+                    f64::INFINITY
+                } else {
+                    estimates.iter().map( |e| if e.count < 2 { 0.0 } else { e.relative_error() } ).sum::<f64>() / estimates.len() as f64
+                };
+
+            eprint!( "Pass {}: {:.1} samples per pixel, noise {:.4}, {:.1}s             \r"
+                   , passes, samples as f64 / estimates.len() as f64, noise, start.elapsed().as_secs_f64() );
+
+            let finished = samples == samples_before
+                        || out_of_time()
+                        || ( self.sample_budget > 0 && samples >= self.sample_budget )
+                        || ( self.noise_target > 0.0 && noise <= self.noise_target );
 
-            let display_color = self.pixel_samples_scale * pixel_color;
-            write_color( &mut stdout(), &display_color );
+            if finished || last_write.elapsed().as_secs_f64() >= self.progress_interval {
+                self.write_estimates( path, &estimates )?;
+                last_write = Instant::now();
+            }
+
+            if finished { break; }
+
+            if passes > 1 {
+                pass_size = i32::min( 2 * pass_size, 64 );
+            }
         }
 
-        eprint!( "\r                                      \r" ); stdout().flush().unwrap();
-        eprint!( "Done!\n" );
+        self.write_heatmap( &estimates.iter().map( |e| e.count ).collect::<Vec<i32>>() );
+
+        eprint!( "\nDone!\n" );
+
+        Ok( () )
+    }
+
+    // Writes a whole image, through a temporary file so that viewers never
+    // catch it half written.
+    fn write_estimates( &self, path: &str, estimates: &[Pixel_estimate] ) -> io::Result<()> {
+
+        let ( width, height ) = self.output_size();
+        let temporary         = format!( "{}.part", path );
+
+        let mut output = BufWriter::new( File::create( &temporary )? );
+
+        write_header( &mut output, width, height, self.has_alpha() );
+        for estimate in estimates {
+            self.write_pixel( &mut output, estimate );
+        }
+
+        output.flush()?;
+        drop( output );
+
+        fs::rename( &temporary, path )
+    }
+
+    fn sample_pixel( &self, i: i32, j: i32, world: &impl Hittable ) -> Pixel_estimate {
+
+        let mut estimate = Pixel_estimate::new();
+        self.add_samples( i, j, world, &mut estimate, self.samples_per_pixel );
+
+        estimate
+    }
+
+    // Up to count more samples into estimate, carrying on with its sample
+    // indices, and none past samples_per_pixel or once adaptive sampling finds
+    // it converged.
+    fn add_samples( &self, i: i32, j: i32, world: &impl Hittable, estimate: &mut Pixel_estimate, count: i32 ) {
+
+        let last = i32::min( estimate.count + count, self.samples_per_pixel );
+
+        while estimate.count < last && !self.converged( estimate ) {
+
+            start_pixel_sample( &self.sampler, i, j, estimate.count as u32, self.samples_per_pixel as u32 );
+
+            start_dimensions( FILM_DIMENSION, 2 );
+            let ( u, v )           = random_2d();
+            let ( dx, dy, weight ) = self.filter_sampler.sample( u, v );
+
+            start_dimensions( LENS_DIMENSION, LENS_DIMENSIONS );
+
+            let ( inside, r ) = self.get_ray( i, j, Vec3f( dx, dy, 0.0 ));
+            let color         = if inside { self.ray_color( &r, self.max_depth, world ) } else { Color( 0.0, 0.0, 0.0 ) };
+
+            estimate.add( &color, weight, inside );
+        }
+
+        end_pixel_samples();
+    }
+
+    fn converged( &self, estimate: &Pixel_estimate ) -> bool {
+        self.adaptive_threshold > 0.0
+        && estimate.count >= self.min_samples_per_pixel
+        && estimate.relative_error() <= self.adaptive_threshold
+    }
+
+    fn write_pixel( &self, output: &mut impl Write, estimate: &Pixel_estimate ) {
+
+        let black = Color( 0.0, 0.0, 0.0 );
+
+        if !self.has_alpha() {
+            let color = if estimate.total_weight != 0.0 { estimate.color / estimate.total_weight } else { black };
+            write_color( output, &color );
+            return
+        }
+
+        let color = if estimate.covered_weight != 0.0 { estimate.color / estimate.covered_weight } else { black };
+        let alpha = if estimate.total_weight != 0.0 { estimate.covered_weight / estimate.total_weight } else { 0.0 };
+
+        write_color_alpha( output, &color, alpha );
+    }
+
+    // Samples each pixel took, from black through red and yellow up to white
+    // at samples_per_pixel.
+    fn write_heatmap( &self, counts: &[i32] ) {
+
+        let Some( path ) = &self.heatmap_path else { return };
+
+        let ( width, height ) = self.output_size();
+
+        let mut text = format!( "P3\n{} {}\n255\n", width, height );
+
+        for count in counts {
+            let t       = *count as f64 / self.samples_per_pixel as f64;
+            let channel = | x: f64 | ( 255.0 * f64::clamp( x, 0.0, 1.0 )) as u8;
+
+            text += &format!( "{} {} {}\n", channel( 3.0 * t ), channel( 3.0 * t - 1.0 ), channel( 3.0 * t - 2.0 ));
+        }
+
+        if let Err( error ) = fs::write( path, text ) {
+            eprintln!( "Can't write {}: {}", path, error );
+        }
     }
 
     pub fn initializer( &mut self ) {
 
-        self.image_height = ( self.image_width as f64 / self.aspect_ratio ) as i32;
+        // Panoramas have a fixed shape.
+        self.image_height = match self.projection {
+            Projection::Equirectangular => self.image_width / 2,
+            Projection::Cube_map        => self.image_width / 6,
+            _                           => ( self.image_width as f64 / self.aspect_ratio ) as i32,
+        };
         self.image_height = if self.image_height < 1 { 1 } else { self.image_height };
 
-        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
+        self.filter_sampler = Arc::new( Filter_sampler::new( self.filter ));
 
         self.center = self.lookfrom;
 
-        let theta           = degrees_to_radians( self.vfov );
-        let h               = f64::tan( theta / 2.0 );
-        let viewport_height = 2.0 * h * self.focus_dist;
-        let viewport_width  = viewport_height * ( self.image_width as f64 / self.image_height as f64 );
+        let aspect = self.image_width as f64 / self.image_height as f64;
+
+        // The viewport lies on the focus plane either way.
+        let ( viewport_width, viewport_height ) = match self.projection {
+            Projection::Perspective => {
+                let theta           = degrees_to_radians( self.vfov );
+                let h               = f64::tan( theta / 2.0 );
+                let viewport_height = 2.0 * h * self.focus_dist;
+                ( viewport_height * aspect, viewport_height )
+            }
+            Projection::Orthographic => ( self.view_width, self.view_width / aspect ),
+            _                        => ( 0.0, 0.0 ),
+        };
 
         let w = ( self.lookfrom - self.lookat ).normalize();
         let u = cross( self.vup, w ).normalize();
         let v = cross( w, u );
 
+        ( self.u, self.v, self.w ) = ( u, v, w );
+
         let viewport_u = viewport_width * u;
         let viewport_v = viewport_height * -v;
 
@@ -181,7 +613,8 @@ impl Camera {
         self.pixel_delta_v = viewport_v / self.image_height as f64;
 
         let viewport_upper_left = self.center - self.focus_dist * w
-                                - viewport_u / 2.0 - viewport_v / 2.0;
+                                - viewport_u / 2.0 - viewport_v / 2.0
+                                + self.shift_x * viewport_u - self.shift_y * viewport_v;
 
         self.pixel00_loc = viewport_upper_left
                          + 0.5 * ( self.pixel_delta_u + self.pixel_delta_v );
@@ -191,59 +624,352 @@ impl Camera {
 
         self.defocus_disk_u = u * defocus_radius;
         self.defocus_disk_v = v * defocus_radius;
+
+        let tilt      = degrees_to_radians( self.tilt );
+        let direction = degrees_to_radians( self.tilt_direction );
+        let axis      = f64::cos( direction ) * u + f64::sin( direction ) * v;
+
+        self.focus_normal = f64::cos( tilt ) * w + f64::sin( tilt ) * cross( w, axis );
+
+        if let Some( lens ) = &mut self.lens {
+            lens.initializer( self.focus_dist, aspect );
+        }
     }
 
-    fn get_ray( &self, i: i32, j: i32 ) -> Ray {
-        
-        let offset = Camera::sample_square();
+    // Size of the written image, holding both eyes in stereo.
+    fn output_size( &self ) -> ( i32, i32 ) {
+        match self.stereo {
+            Stereo_layout::Mono         => ( self.image_width, self.image_height ),
+            Stereo_layout::Side_by_side => ( 2 * self.image_width, self.image_height ),
+            Stereo_layout::Top_bottom   => ( self.image_width, 2 * self.image_height ),
+        }
+    }
+
+    // Eye of an output pixel, -1 left, 1 right and 0 in mono, and the pixel
+    // within that eye's image.
+    fn eye_of( &self, i: i32, j: i32 ) -> ( f64, i32, i32 ) {
+        match self.stereo {
+            Stereo_layout::Mono         => ( 0.0, i, j ),
+            Stereo_layout::Side_by_side => if i < self.image_width  { ( -1.0, i, j ) } else { ( 1.0, i - self.image_width, j ) },
+            Stereo_layout::Top_bottom   => if j < self.image_height { ( -1.0, i, j ) } else { ( 1.0, i, j - self.image_height ) },
+        }
+    }
+
+    // Whether the projection can leave pixels uncovered, needing an alpha channel.
+    pub fn has_alpha( &self ) -> bool {
+        matches!( self.projection, Projection::Fisheye { .. } )
+    }
+
+    // Ray through offset from the pixel's center, false when it falls outside
+    // the projection's image.
+    fn get_ray( &self, i: i32, j: i32, offset: Vec3f ) -> ( bool, Ray ) {
+
+        let ( eye, i, j ) = self.eye_of( i, j );
+        let eye_offset    = ( 0.5 * eye * self.interocular_distance ) * self.u;
+
+        if let Some( lens ) = &self.lens {
+            let ( passed, origin, direction ) = lens.generate_ray(( i as f64 + 0.5 + offset.x() ) / self.image_width as f64
+                                                                 , ( j as f64 + 0.5 + offset.y() ) / self.image_height as f64 );
+
+            let origin    = self.center + eye_offset + origin.x() * self.u + origin.y() * self.v + origin.z() * self.w;
+            let direction = direction.x() * self.u + direction.y() * self.v + direction.z() * self.w;
+
+            return ( passed, Ray( origin, direction ))
+        }
+
+        if matches!( self.projection, Projection::Perspective | Projection::Orthographic ) {
+            return ( true, self.get_planar_ray( i, j, offset, eye_offset ))
+        }
+
+        let x = ( i as f64 + 0.5 + offset.x() ) / self.image_width as f64;
+        let y = ( j as f64 + 0.5 + offset.y() ) / self.image_height as f64;
+
+        let ( covered, local ) = self.panoramic_direction( x, y );
+        let direction          = local.x() * self.u + local.y() * self.v + local.z() * self.w;
+
+        // Omnidirectional stereo puts the eye to the side of each direction,
+        // on the horizontal circle the eyes sweep when turning the head.
+        let eye_offset =
+            if self.omnidirectional_stereo && self.projection == Projection::Equirectangular {
+                let side = Vec3f( -local.z(), 0.0, local.x() );
+                if side.near_zero() {
+                    Vec3f( 0.0, 0.0, 0.0 )
+                } else {
+                    let side = side.normalize();
+                    ( 0.5 * eye * self.interocular_distance ) * ( side.x() * self.u + side.z() * self.w )
+                }
+            } else {
+                eye_offset
+            };
+
+        ( covered, Ray( self.center + eye_offset, direction ))
+    }
+
+    // Direction in the camera's frame, x right, y up and z backwards, for the
+    // image position x, y in [0, 1], y going down.
+    fn panoramic_direction( &self, x: f64, y: f64 ) -> ( bool, Vec3f ) {
+
+        match self.projection {
+
+            Projection::Equirectangular => {
+                let theta = y * math::PI;
+                let phi   = ( x - 0.5 ) * 2.0 * math::PI;
+
+                ( true, Vec3f( f64::sin( theta ) * f64::sin( phi ), f64::cos( theta ), -f64::sin( theta ) * f64::cos( phi )))
+            }
+
+            Projection::Fisheye { fov, mapping } => {
+                let radius = 0.5 * f64::min( self.image_width as f64, self.image_height as f64 );
+                let a      = ( x - 0.5 ) * self.image_width as f64 / radius;
+                let b      = ( 0.5 - y ) * self.image_height as f64 / radius;
+                let r      = f64::sqrt( a * a + b * b );
+
+                if r > 1.0 {
+                    return ( false, Vec3f( 0.0, 0.0, -1.0 ))
+                }
+
+                let theta_max = 0.5 * degrees_to_radians( fov );
+                let theta     = match mapping {
+                    Fisheye_mapping::Equidistant => r * theta_max,
+                    Fisheye_mapping::Equisolid   => 2.0 * f64::asin( r * f64::sin( 0.5 * theta_max )),
+                };
+
+                if r == 0.0 {
+                    return ( true, Vec3f( 0.0, 0.0, -1.0 ))
+                }
+
+                let sin_theta = f64::sin( theta );
+                ( true, Vec3f( sin_theta * a / r, sin_theta * b / r, -f64::cos( theta )))
+            }
+
+            _ => {
+                let face = usize::min(( x * 6.0 ) as usize, 5 );
+                let s    = 2.0 * ( x * 6.0 - face as f64 ) - 1.0;
+                let t    = 2.0 * y - 1.0;
+
+                let direction = match face {
+                    0 => Vec3f(  1.0,   -t,   -s ),
+                    1 => Vec3f( -1.0,   -t,    s ),
+                    2 => Vec3f(    s,  1.0,    t ),
+                    3 => Vec3f(    s, -1.0,   -t ),
+                    4 => Vec3f(    s,   -t,  1.0 ),
+                    _ => Vec3f(   -s,   -t, -1.0 ),
+                };
+
+                ( true, direction.normalize() )
+            }
+        }
+    }
+
+    // eye_offset moves the viewpoint sideways for stereo, while the window at
+    // convergence_dist stays put, making the frustum asymmetric.
+    fn get_planar_ray( &self, i: i32, j: i32, offset: Vec3f, eye_offset: Vec3f ) -> Ray {
 
         let pixel_sample = self.pixel00_loc
                          + (( i as f64 + offset.x() ) * self.pixel_delta_u )
                          + (( j as f64 + offset.y() ) * self.pixel_delta_v );
         
-        let ray_origin =
+        let lens_offset =
             if self.defocus_angle <= 0.0 {
-                self.center
+                Vec3f( 0.0, 0.0, 0.0 )
+            } else {
+                self.defocus_disk_sample(( i as f64 + 0.5 + offset.x() ) / self.image_width as f64
+                                       , ( j as f64 + 0.5 + offset.y() ) / self.image_height as f64 ) - self.center
+            };
+
+        // Orthographic rays start from the camera plane straight behind their
+        // pixel, so they stay parallel but for the lens.
+        let ray_origin = match self.projection {
+            Projection::Orthographic => pixel_sample + self.focus_dist * self.w + lens_offset,
+            _                        => self.center + lens_offset,
+        };
+
+        // A tilted focus plane through the same point on the axis, met where the
+        // pixel's central ray crosses it.
+        let pixel_sample =
+            if self.tilt == 0.0 {
+                pixel_sample
             } else {
-                self.defocus_disk_sample()
+                let base    = ray_origin - lens_offset;
+                let towards = pixel_sample - base;
+                let denom   = dot( self.focus_normal, towards );
+                let t       = dot( self.focus_normal, self.center - self.focus_dist * self.w - base ) / denom;
+
+                if denom.abs() < 1e-12 || t <= 0.0 { pixel_sample } else { base + t * towards }
             };
-        let ray_direction = pixel_sample - ray_origin;
+
+        let ray_origin    = ray_origin + eye_offset;
+        let focus_point   = pixel_sample + ( 1.0 - self.focus_dist / self.convergence_dist ) * eye_offset;
+        let ray_direction = focus_point - ray_origin;
 
         Ray( ray_origin, ray_direction )
     }
 
-    const fn sample_square() -> Vec3f {
-        Vec3f( random_double() - 0.5, random_double() - 0.5, 0.0 )
+    // Point on the aperture seen from the image position x, y in [0, 1], y going
+    // down, which matters for the cat's eye.
+    fn defocus_disk_sample( &self, x: f64, y: f64 ) -> Point3 {
+
+        let width    = self.image_width as f64;
+        let height   = self.image_height as f64;
+        let diagonal = f64::sqrt( width * width + height * height );
+
+        let clip_x = self.cat_eye * ( 2.0 * x - 1.0 ) * width / diagonal;
+        let clip_y = self.cat_eye * ( 1.0 - 2.0 * y ) * height / diagonal;
+
+        // Rejection for masks and the cat's eye, giving up on the center for
+        // apertures too thin to hit.
+        for _ in 0..256 {
+
+            let ( open, p ) = self.aperture_sample();
+
+            let clipped = self.cat_eye != 0.0
+                       && ( p.0 - clip_x ) * ( p.0 - clip_x ) + ( p.1 - clip_y ) * ( p.1 - clip_y ) > 1.0;
+
+            if open && !clipped {
+                return self.center + p.0 * self.defocus_disk_u + p.1 * self.defocus_disk_v
+            }
+        }
+
+        self.center
+    }
+
+    // One try at a point of the aperture, false when it lands on a closed part.
+    fn aperture_sample( &self ) -> ( bool, Vec3f ) {
+
+        match &self.aperture {
+
+            Aperture::Disk => ( true, Vec3f::random_in_unit_disk() ),
+
+            // Uniform over the triangle between the center and a random side,
+            // the first number picking the side and then reused.
+            Aperture::Polygon { blades, rotation } => {
+                let blades   = u32::max( *blades, 3 ) as f64;
+                let ( a, b ) = random_2d();
+                let side     = f64::min( f64::floor( a * blades ), blades - 1.0 );
+                let a        = a * blades - side;
+
+                let a0 = degrees_to_radians( *rotation ) + 2.0 * math::PI * side / blades;
+                let a1 = a0 + 2.0 * math::PI / blades;
+                let r  = f64::sqrt( a );
+
+                ( true, Vec3f( r * (( 1.0 - b ) * f64::cos( a0 ) + b * f64::cos( a1 ))
+                             , r * (( 1.0 - b ) * f64::sin( a0 ) + b * f64::sin( a1 )), 0.0 ))
+            }
+
+            Aperture::Mask( image ) => {
+                let ( a, b ) = random_2d();
+                let p        = Vec3f( 2.0 * a - 1.0, 2.0 * b - 1.0, 0.0 );
+
+                let x = (( p.0 + 1.0 ) * 0.5 * image.width() as f64 ) as usize;
+                let y = (( 1.0 - p.1 ) * 0.5 * image.height() as f64 ) as usize;
+
+                let texel    = image.pixel( x, y );
+                let openness = ( texel.x() + texel.y() + texel.z() ) / 3.0;
+
+                ( random_double() < openness, p )
+            }
+        }
     }
 
-    fn defocus_disk_sample( &self ) -> Point3 {
-        let p = Vec3f::random_in_unit_disk();
-        self.center + p.0 * self.defocus_disk_u + p.1 * self.defocus_disk_v
+    // In spectral mode every path carries random wavelengths. Materials and
+    // lights give their values there, other colors along it are upsampled to
+    // their spectrum's values.
+    fn ray_color( &self, r: &Ray, depth: i32, world: &impl Hittable ) -> Color {
+
+        if !self.spectral {
+            return self.path_color( r, depth, world, 0.0 )
+        }
+
+        start_dimensions( WAVELENGTH_DIMENSION, 1 );
+
+        let lambda = sample_wavelength();
+        let value  = self.path_color( &r.with_wavelength( lambda ), depth, world, 0.0 );
+
+        spectral_to_rgb( &value, lambda )
     }
 
-    fn ray_color( r: &Ray, depth: i32, world: &impl Hittable) -> Color {
+    // scatter_pdf is the pdf the previous bounce picked r with, zero for camera
+    // rays and specular bounces, and weights the background against light sampling.
+    fn path_color( &self, r: &Ray, depth: i32, world: &impl Hittable, scatter_pdf: f64 ) -> Color {
 
         if depth <= 0 {
             return Color( 0.0, 0.0, 0.0 )
         }
 
+        start_bounce(( self.max_depth - depth ) as u32 );
+
         let ( bool_ret, rec ) = world.hit( r, &Interval::new( 0.001, math::INFINITY ));
 
         if bool_ret {
 
-            let ( bool_scat, attenuation, scattered ) = rec.mat.scatter( r, &rec );
+            let lambda        = r.wavelength();
+            let transmittance = at_wavelength( &r.media().transmittance( rec.t * r.direction().norm() ), lambda );
+            let emitted       = rec.mat.emitted( r, &rec );
+            let direct        = self.direct_lighting( r, &rec, world );
+
+            let ( bool_scat, attenuation, scattered, pdf ) = rec.mat.scatter_with_pdf( r, &rec );
+            let mut attenuation = attenuation;
+            let scattered       = scattered.with_origin( rec.spawn_point( scattered.direction() ));
+
+            if scattered.hero_only() && !r.hero_only() {
+                attenuation = attenuation * hero_only_weight();
+            }
 
             if bool_scat {
-                return attenuation * Camera::ray_color( &scattered, depth - 1, world )
+                return transmittance * ( emitted + direct + attenuation * self.path_color( &scattered, depth - 1, world, pdf ))
             }
 
-            return Color( 0.0, 0.0, 0.0 )
+            return transmittance * ( emitted + direct )
         }
 
         let unit_direction = r.direction().normalize();
-        let a              = 0.5 * ( unit_direction.y() + 1.0 );
+        let background     = illuminant_at_wavelength( &self.background.value( unit_direction ), r.wavelength() );
+
+        if scatter_pdf > 0.0 {
+            return power_heuristic( scatter_pdf, self.background.pdf( unit_direction )) * background
+        }
 
-        ( 1.0 - a ) * Color( 1.0, 1.0, 1.0 ) + a * Color( 0.5, 0.7, 1.0 )
+        background
+    }
+
+    fn direct_lighting( &self, r: &Ray, rec: &Hit_record, world: &impl Hittable ) -> Color {
+
+        let lambda     = r.wavelength();
+        let mut direct = Color( 0.0, 0.0, 0.0 );
+
+        for light in &self.lights {
+
+            let ( wi, li, distance ) = light.sample_li( rec.p, lambda );
+            if li.near_zero() { continue; }
+
+            let f = rec.mat.eval( r, rec, wi );
+            if f.near_zero() { continue; }
+
+            if !self.occluded( rec, wi, distance, world ) {
+                direct += f * li;
+            }
+        }
+
+        let ( wi, le, light_pdf ) = self.background.sample();
+
+        if light_pdf > 0.0 {
+
+            let f = rec.mat.eval( r, rec, wi );
+
+            if !f.near_zero() && !self.occluded( rec, wi, math::INFINITY, world ) {
+                let weight = power_heuristic( light_pdf, rec.mat.scattering_pdf( r, rec, wi ));
+                direct += ( weight / light_pdf ) * f * illuminant_at_wavelength( &le, lambda );
+            }
+        }
+
+        direct
+    }
+
+    // Shadow rays leave from the side of the true surface the light is on.
+    fn occluded( &self, rec: &Hit_record, wi: Vec3f, distance: f64, world: &impl Hittable ) -> bool {
+        let shadow_ray      = Ray( rec.spawn_point( wi ), wi );
+        let ( occluded, _ ) = world.hit( &shadow_ray, &Interval::new( 0.001, distance - 0.001 ));
+        occluded
     }
 }
 
@@ -258,19 +984,21 @@ impl Multithread_camera {
     }
 
     pub fn render_multithread( &self, world: &'static impl Hittable ) {
+        self.render_multithread_to( world, &mut stdout() );
+    }
+
+    pub fn render_multithread_to( &self, world: &'static impl Hittable, output: &mut impl Write ) {
 
         let local_camera = self.camera.clone();
 
         //local_camera.lock().unwrap().initializer();
-        let image_width         = local_camera.image_width;
-        let image_height        = local_camera.image_height;
-        let samples_per_pixel   = local_camera.samples_per_pixel;
-        let max_depth           = local_camera.max_depth;
-        let pixel_samples_scale = local_camera.pixel_samples_scale;
+        let ( image_width, image_height ) = local_camera.output_size();
 
         let number_of_threads = 4;
         
-        println!( "P3\n{} {}\n255", image_width, image_height );
+        write_header( output, image_width, image_height, local_camera.has_alpha() );
+
+        let mut counts = Vec::new();
 
         let total = image_height * image_width;
 
@@ -294,15 +1022,7 @@ impl Multithread_camera {
                     let local_camera = self.camera.clone();
 
                     let h = thread::spawn(
-                        move || {
-                            let mut pixel_color = Color( 0.0, 0.0, 0.0 );
-                            for sample in 0..samples_per_pixel {
-                                let r = local_camera.get_ray( i, j );
-                                pixel_color += Camera::ray_color( &r, max_depth, world );
-                            }
-
-                            pixel_color
-                        });
+                        move || local_camera.sample_pixel( i, j, world ));
 
                     handles.push( h );
                 }
@@ -311,13 +1031,61 @@ impl Multithread_camera {
             };
 
             for handle in handles {
-                let pixel_color   = handle.join().unwrap();
-                let display_color = pixel_samples_scale * pixel_color;
-                write_color( &mut stdout(), &display_color );
+                let estimate = handle.join().unwrap();
+                local_camera.write_pixel( output, &estimate );
+                counts.push( estimate.count );
             }
         }
 
+        local_camera.write_heatmap( &counts );
+
         eprint!( "\r                                      \r" ); stdout().flush().unwrap();
         eprint!( "Done!\n" );
     }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn estimate_of( luminances: &[f64] ) -> Pixel_estimate {
+        let mut estimate = Pixel_estimate::new();
+        for y in luminances {
+            estimate.add( &Color( *y, *y, *y ), 1.0, true );
+        }
+        estimate
+    }
+
+    #[test]
+    fn relative_error_needs_two_samples() {
+        assert_eq!( estimate_of( &[] ).relative_error(), f64::INFINITY );
+        assert_eq!( estimate_of( &[ 0.5 ] ).relative_error(), f64::INFINITY );
+    }
+
+    #[test]
+    fn relative_error_of_a_constant_pixel_is_zero() {
+        assert_eq!( estimate_of( &[ 0.3, 0.3, 0.3, 0.3 ] ).relative_error(), 0.0 );
+    }
+
+    #[test]
+    fn relative_error_is_the_confidence_interval_over_the_mean() {
+        // Mean 0.5, sample variance 0.5.
+        assert!(( estimate_of( &[ 0.0, 1.0 ] ).relative_error() - 1.96 ).abs() < 1e-9 );
+    }
+
+    #[test]
+    fn relative_error_is_floored_for_dark_pixels() {
+        let expected = 1.96 * f64::sqrt( 2e-6 / 2.0 ) * 256.0;
+        assert!(( estimate_of( &[ 0.0, 0.002 ] ).relative_error() - expected ).abs() < 1e-9 );
+    }
+
+    #[test]
+    fn uncovered_samples_count_as_black() {
+
+        let mut estimate = estimate_of( &[ 1.0 ] );
+        estimate.add( &Color( 1.0, 1.0, 1.0 ), 1.0, false );
+
+        assert!(( estimate.relative_error() - 1.96 ).abs() < 1e-9 );
+    }
 }
\ No newline at end of file
@@ -0,0 +1,423 @@
+use std::fs;
+use std::io;
+
+use crate::raytracer::math::utilities::random_double;
+use crate::raytracer::math::vec3f::*;
+use crate::raytracer::microfacet::refract_through;
+use crate::raytracer::sampler::random_2d;
+
+// One spherical interface of a lens prescription, all lengths in mm. radius
+// is positive when the center of curvature is on the film side, zero for the
+// aperture stop. thickness and ior describe the gap towards the next element.
+#[derive( Clone )]
+struct Lens_element {
+    radius: f64,
+    thickness: f64,
+    ior: f64,
+    aperture_radius: f64,
+}
+
+// Thick lens system traced ray by ray, after Kolb et al. "A Realistic Camera
+// Model for Computer Graphics". In lens space the film lies at z = 0 and the
+// scene towards +z.
+#[derive( Clone )]
+pub struct Lens_system {
+
+    // Sensor diagonal in mm, 35mm full frame by default.
+    pub film_diagonal: f64,
+    // World units per mm.
+    pub scale: f64,
+
+    elements: Vec<Lens_element>,
+    // z of each element's vertex, front to back, kept in step with the
+    // thicknesses.
+    element_z: Vec<f64>,
+
+    film_width: f64,
+    film_height: f64,
+
+    // Exit pupil bounds on the rear element's plane, as min x, max x, min y,
+    // max y, for film points along +x at increasing distances from the axis.
+    pupil_bounds: Vec<[ f64; 4 ]>,
+    max_pupil_area: f64,
+    // Focus distance in mm and film width and height the bounds were last
+    // computed for.
+    pupil_key: Option<( f64, f64, f64 )>,
+}
+
+const PUPIL_BINS: usize    = 32;
+const PUPIL_SAMPLES: usize = 128;
+
+impl Lens_system {
+
+    // Reads one element per line, front to back: radius, thickness, ior and
+    // aperture diameter. '#' starts a comment, an ior of 0 means air.
+    pub fn load( path: &str ) -> io::Result<Lens_system> {
+
+        let text    = fs::read_to_string( path )?;
+        let invalid = | message: &str | io::Error::new( io::ErrorKind::InvalidData, message.to_string() );
+
+        let mut elements = Vec::new();
+
+        for line in text.lines() {
+
+            let line = line.split( '#' ).next().unwrap_or( "" ).trim();
+            if line.is_empty() { continue; }
+
+            let values = line.split_whitespace()
+                             .map( |v| v.parse::<f64>().map_err( |_| invalid( "bad lens value" )))
+                             .collect::<io::Result<Vec<f64>>>()?;
+
+            if values.len() != 4 {
+                return Err( invalid( "lens elements need radius, thickness, ior and aperture" ))
+            }
+
+            elements.push( Lens_element { radius: values[0]
+                                        , thickness: values[1]
+                                        , ior: if values[2] == 0.0 { 1.0 } else { values[2] }
+                                        , aperture_radius: 0.5 * values[3] } );
+        }
+
+        if elements.is_empty() {
+            return Err( invalid( "empty lens prescription" ))
+        }
+
+        let mut lens = Lens_system { film_diagonal: 43.27
+                                   , scale: 0.001
+                                   , elements
+                                   , element_z: Vec::new()
+                                   , film_width: 0.0
+                                   , film_height: 0.0
+                                   , pupil_bounds: Vec::new()
+                                   , max_pupil_area: 0.0
+                                   , pupil_key: None };
+
+        lens.compute_element_z();
+
+        Ok( lens )
+    }
+
+    // Focuses on focus_dist, in world units from the film, and sizes the film
+    // for the image's aspect ratio. Sequences call it every frame, so the
+    // costly part only runs when the focus or the film changed.
+    pub fn initializer( &mut self, focus_dist: f64, aspect: f64 ) {
+
+        self.film_height = self.film_diagonal / f64::sqrt( 1.0 + aspect * aspect );
+        self.film_width  = self.film_height * aspect;
+
+        let key = ( focus_dist / self.scale, self.film_width, self.film_height );
+        if self.pupil_key == Some( key ) {
+            return
+        }
+
+        self.focus( key.0 );
+        self.compute_pupil_bounds();
+
+        self.pupil_key = Some( key );
+    }
+
+    // Ray for the image position s, t in [0, 1], t going down, in the camera's
+    // frame: x right, y up and z backwards, in world units. False when it gets
+    // stopped inside the lens, or randomly to account for the pupil's size.
+    pub fn generate_ray( &self, s: f64, t: f64 ) -> ( bool, Point3, Vec3f ) {
+
+        let failed = ( false, Point3( 0.0, 0.0, 0.0 ), Vec3f( 0.0, 0.0, -1.0 ));
+
+        // The lens flips the image.
+        let film = Point3( -( s - 0.5 ) * self.film_width, ( t - 0.5 ) * self.film_height, 0.0 );
+
+        let r      = f64::sqrt( film.x() * film.x() + film.y() * film.y() );
+        let bin    = usize::min(( r / ( 0.5 * self.film_diagonal ) * PUPIL_BINS as f64 ) as usize, PUPIL_BINS - 1 );
+        let bounds = self.pupil_bounds[bin];
+        let area   = ( bounds[1] - bounds[0] ) * ( bounds[3] - bounds[2] );
+
+        if area <= 0.0 {
+            return failed
+        }
+
+        // Bounds were found along +x, turn them towards the film point.
+        let ( a, b )     = random_2d();
+        let px           = bounds[0] + a * ( bounds[1] - bounds[0] );
+        let py           = bounds[2] + b * ( bounds[3] - bounds[2] );
+        let ( sin, cos ) = if r > 0.0 { ( film.y() / r, film.x() / r ) } else { ( 0.0, 1.0 ) };
+        let pupil        = Point3( cos * px - sin * py, sin * px + cos * py, self.rear_z() );
+
+        let direction = ( pupil - film ).normalize();
+
+        // Irradiance falls off with cos^4 and with the pupil's visible area.
+        let cos4 = f64::powi( direction.z(), 4 );
+        if random_double() >= cos4 * area / self.max_pupil_area {
+            return failed
+        }
+
+        let ( passed, origin, direction ) = self.trace_from_film( film, direction );
+        if !passed {
+            return failed
+        }
+
+        ( true
+        , self.scale * Point3( origin.x(), origin.y(), -origin.z() )
+        , Vec3f( direction.x(), direction.y(), -direction.z() ))
+    }
+
+    fn rear_z( &self ) -> f64 {
+        self.elements.last().map_or( 0.0, |e| e.thickness )
+    }
+
+    fn compute_element_z( &mut self ) {
+
+        let mut at = self.elements.iter().map( |e| e.thickness ).sum::<f64>();
+
+        self.element_z = self.elements.iter().map( |element| {
+            let z = at;
+            at   -= element.thickness;
+            z
+        }).collect();
+    }
+
+    // Intersection with element's surface whose vertex is at z_vertex, its
+    // normal facing against the ray.
+    fn intersect( element: &Lens_element, z_vertex: f64, origin: Point3, direction: Vec3f ) -> ( bool, Point3, Vec3f ) {
+
+        let missed = ( false, origin, direction );
+
+        if element.radius == 0.0 {
+            if direction.z() == 0.0 { return missed }
+            let t = ( z_vertex - origin.z() ) / direction.z();
+            if t <= 0.0 { return missed }
+            return ( true, origin + t * direction, Vec3f( 0.0, 0.0, -direction.z().signum() ))
+        }
+
+        let center = Point3( 0.0, 0.0, z_vertex - element.radius );
+        let oc     = origin - center;
+        let b      = dot( oc, direction );
+        let c      = oc.norm_sq() - element.radius * element.radius;
+        let disc   = b * b - c;
+
+        if disc < 0.0 {
+            return missed
+        }
+
+        // The cap around the vertex, not the far side of the sphere.
+        let sqrtd = f64::sqrt( disc );
+        for t in [ -b - sqrtd, -b + sqrtd ] {
+
+            let p = origin + t * direction;
+
+            if t > 1e-9 && ( p.z() - center.z() ) * element.radius > 0.0 {
+                let n = ( p - center ).normalize();
+                return ( true, p, if dot( n, direction ) > 0.0 { -n } else { n } )
+            }
+        }
+
+        missed
+    }
+
+    // Passes the ray through the element, from the medium of IOR eta_i into
+    // the one of IOR eta_t.
+    fn cross( element: &Lens_element, z_vertex: f64, origin: Point3, direction: Vec3f
+            , eta_i: f64, eta_t: f64 ) -> ( bool, Point3, Vec3f ) {
+
+        let ( hit, p, n ) = Self::intersect( element, z_vertex, origin, direction );
+
+        if !hit || p.x() * p.x() + p.y() * p.y() > element.aperture_radius * element.aperture_radius {
+            return ( false, origin, direction )
+        }
+
+        if element.radius == 0.0 {
+            return ( true, p, direction )
+        }
+
+        let ( refracted, wi ) = refract_through( -direction, n, eta_t / eta_i );
+
+        ( refracted, p, wi.normalize() )
+    }
+
+    fn trace_from_film( &self, origin: Point3, direction: Vec3f ) -> ( bool, Point3, Vec3f ) {
+
+        let z = &self.element_z;
+
+        let ( mut origin, mut direction ) = ( origin, direction );
+
+        for i in ( 0..self.elements.len() ).rev() {
+
+            let eta_t = if i > 0 { self.elements[i - 1].ior } else { 1.0 };
+
+            let ( passed, o, d ) = Self::cross( &self.elements[i], z[i], origin, direction, self.elements[i].ior, eta_t );
+            if !passed {
+                return ( false, origin, direction )
+            }
+
+            ( origin, direction ) = ( o, d );
+        }
+
+        ( true, origin, direction )
+    }
+
+    fn trace_from_scene( &self, origin: Point3, direction: Vec3f ) -> ( bool, Point3, Vec3f ) {
+
+        let z = &self.element_z;
+
+        let ( mut origin, mut direction ) = ( origin, direction );
+
+        for i in 0..self.elements.len() {
+
+            let eta_i = if i > 0 { self.elements[i - 1].ior } else { 1.0 };
+
+            let ( passed, o, d ) = Self::cross( &self.elements[i], z[i], origin, direction, eta_i, self.elements[i].ior );
+            if !passed {
+                return ( false, origin, direction )
+            }
+
+            ( origin, direction ) = ( o, d );
+        }
+
+        ( true, origin, direction )
+    }
+
+    // Moves the film until a paraxial ray from the axis point distance mm in
+    // front of it comes back to the axis on the film.
+    fn focus( &mut self, distance: f64 ) {
+
+        for _ in 0..32 {
+
+            let front  = self.element_z[0];
+            let height = 0.01 * self.elements[0].aperture_radius;
+            let source = Point3( 0.0, 0.0, distance );
+
+            let ( passed, o, d ) = self.trace_from_scene( source, ( Point3( height, 0.0, front ) - source ).normalize() );
+            if !passed || d.x() == 0.0 {
+                return
+            }
+
+            // Where the ray crosses the axis, relative to the film.
+            let z_cross = o.z() - o.x() * d.z() / d.x();
+
+            let last = self.elements.len() - 1;
+            self.elements[last].thickness = f64::max( self.elements[last].thickness - z_cross, 1e-3 );
+            self.compute_element_z();
+
+            if f64::abs( z_cross ) < 1e-6 {
+                return
+            }
+        }
+    }
+
+    fn compute_pupil_bounds( &mut self ) {
+
+        let rear   = self.elements.last().map_or( 0.0, |e| e.aperture_radius );
+        let rear_z = self.rear_z();
+        let cell   = 2.0 * rear / PUPIL_SAMPLES as f64;
+
+        self.pupil_bounds = ( 0..PUPIL_BINS ).map( |bin| {
+
+            let x    = ( bin as f64 + 0.5 ) / PUPIL_BINS as f64 * 0.5 * self.film_diagonal;
+            let film = Point3( x, 0.0, 0.0 );
+
+            let mut bounds = [ f64::INFINITY, -f64::INFINITY, f64::INFINITY, -f64::INFINITY ];
+
+            for a in 0..PUPIL_SAMPLES {
+                for b in 0..PUPIL_SAMPLES {
+
+                    let px = -rear + ( a as f64 + 0.5 ) * cell;
+                    let py = -rear + ( b as f64 + 0.5 ) * cell;
+
+                    let ( passed, _, _ ) = self.trace_from_film( film, ( Point3( px, py, rear_z ) - film ).normalize() );
+
+                    if passed {
+                        bounds = [ f64::min( bounds[0], px ), f64::max( bounds[1], px )
+                                 , f64::min( bounds[2], py ), f64::max( bounds[3], py ) ];
+                    }
+                }
+            }
+
+            if bounds[0] > bounds[1] {
+                return [ 0.0, 0.0, 0.0, 0.0 ]
+            }
+
+            // Grid points are cell centers, grow by a cell to be safe.
+            [ bounds[0] - cell, bounds[1] + cell, bounds[2] - cell, bounds[3] + cell ]
+        }).collect();
+
+        self.max_pupil_area = self.pupil_bounds.iter()
+                                               .map( |b| ( b[1] - b[0] ) * ( b[3] - b[2] ))
+                                               .fold( 0.0, f64::max );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn load_text( name: &str, text: &str ) -> io::Result<Lens_system> {
+
+        let path = std::env::temp_dir().join( format!( "{}_{}", std::process::id(), name ));
+        fs::write( &path, text ).unwrap();
+
+        let lens = Lens_system::load( path.to_str().unwrap() );
+        fs::remove_file( &path ).unwrap();
+
+        lens
+    }
+
+    // Single 50mm biconvex element with the stop right behind it.
+    const SINGLET: &str = "# radius thickness ior aperture\n\
+                           51.0  5.0 1.5 20.0\n\
+                          -51.0  1.0 0.0 20.0\n\
+                           0.0  48.0 0.0 16.0\n";
+
+    fn error_kind( lens: io::Result<Lens_system> ) -> io::ErrorKind {
+        lens.err().map( |e| e.kind() ).unwrap()
+    }
+
+    #[test]
+    fn loads_a_prescription() {
+        let lens = load_text( "singlet.lens", SINGLET ).unwrap();
+        assert_eq!( lens.elements.len(), 3 );
+        assert_eq!( lens.elements[1].ior, 1.0 );
+        assert_eq!( lens.elements[2].aperture_radius, 8.0 );
+    }
+
+    #[test]
+    fn missing_file_is_not_found() {
+        assert_eq!( error_kind( Lens_system::load( "/nonexistent/lens.txt" )), io::ErrorKind::NotFound );
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert_eq!( error_kind( load_text( "bad_value.lens", "51.0 5.0 glass 20.0\n" )), io::ErrorKind::InvalidData );
+    }
+
+    #[test]
+    fn rejects_wrong_column_counts() {
+        assert_eq!( error_kind( load_text( "short.lens", "51.0 5.0 1.5\n" )), io::ErrorKind::InvalidData );
+        assert_eq!( error_kind( load_text( "long.lens", "51.0 5.0 1.5 20.0 1.0\n" )), io::ErrorKind::InvalidData );
+    }
+
+    #[test]
+    fn rejects_empty_prescriptions() {
+        assert_eq!( error_kind( load_text( "empty.lens", "# nothing but comments\n\n" )), io::ErrorKind::InvalidData );
+    }
+
+    #[test]
+    fn pupil_bounds_are_kept_until_the_focus_or_film_changes() {
+
+        let mut lens = load_text( "cached.lens", SINGLET ).unwrap();
+
+        lens.initializer( 2.0, 1.5 );
+        assert!( !lens.pupil_bounds.is_empty() );
+
+        // Cleared by hand, so a recomputation would show.
+        lens.pupil_bounds.clear();
+        lens.initializer( 2.0, 1.5 );
+        assert!( lens.pupil_bounds.is_empty() );
+
+        lens.initializer( 3.0, 1.5 );
+        assert!( !lens.pupil_bounds.is_empty() );
+
+        lens.pupil_bounds.clear();
+        lens.initializer( 3.0, 1.0 );
+        assert!( !lens.pupil_bounds.is_empty() );
+    }
+}
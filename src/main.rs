@@ -3,17 +3,36 @@
 
 mod raytracer;
 mod sphere;
+mod quad;
+mod mesh;
 mod camera;
+mod lens;
+mod animation;
 
 use raytracer::hittable_list::Hittable_list;
 
 use raytracer::material::{ Empty_mat
                          , Dielectric
                          , Lambertian
-                         , Metal };
+                         , Metal
+                         , Conductor
+                         , Diffuse_light
+                         , Measured_brdf
+                         , Principled
+                         , Thin_film };
+
+use raytracer::texture::{ Solid_color
+                        , Checker_texture };
 
 use raytracer::color::Color;
 
+use raytracer::background::{ Preetham_sky
+                            , Solid_background };
+
+use raytracer::light::Point_light;
+
+use raytracer::sampler::Sobol_sampler;
+
 use raytracer::Rc;
 use std::sync::Arc;
 
@@ -26,6 +45,11 @@ use raytracer::math::PI;
 use sphere::Sphere;
 use camera::Camera;
 use camera::Multithread_camera;
+use camera::Aperture;
+use lens::Lens_system;
+use animation::{ Camera_animation
+               , Keyframe
+               , Interpolation };
 
 fn main() {
 
@@ -154,3 +178,416 @@ fn one_weekend_last_render() {
     let mut multithread_camera = Multithread_camera::new( cam );
     multithread_camera.render_multithread( world );
 }
+
+fn sun_and_sky_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let material_ground = Arc::new( Lambertian::new( &Color( 0.5, 0.5, 0.5 )));
+    let material_center = Arc::new( Lambertian::new( &Color( 0.1, 0.2, 0.5 )));
+    let material_left   = Arc::new( Dielectric::new( 1.5 ));
+    let material_right  = Arc::new( Metal::new( &Color( 0.8, 0.6, 0.2 ), 0.1 ));
+
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -100.5, -1.0)
+                                    , 100.0, material_ground )));
+    world.add( Arc::new( Sphere::new( Point3( 0.0, 0.0, -1.2)
+                                    , 0.5, material_center )));
+    world.add( Arc::new( Sphere::new( Point3( -1.0, 0.0, -1.0)
+                                    , 0.5, material_left )));
+    world.add( Arc::new( Sphere::new( Point3( 1.0, 0.0, -1.0)
+                                    , 0.5, material_right )));
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 100;
+    cam.max_depth         = 50;
+
+    cam.vfov     = 20.0;
+    cam.lookfrom = Point3( -2.0, 2.0, 1.0 );
+    cam.lookat   = Point3( 0.0, 0.0, -1.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    let sky = Preetham_sky::new( Vec3f( 1.0, 0.6, 0.5 ), 3.0, &Color( 0.3, 0.3, 0.3 ));
+    cam.lights.push( Arc::new( sky.sun_light( 0.27 )));
+    cam.background = Arc::new( sky );
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+fn principled_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    // Plastic, brushed gold, frosted glass and clearcoated velvet-ish paint.
+    let settings = [ ( Color( 0.7, 0.1, 0.1 ), 0.0, 0.3, 0.0, 0.0, 0.0 )
+                   , ( Color( 1.0, 0.8, 0.4 ), 1.0, 0.4, 0.0, 0.0, 0.0 )
+                   , ( Color( 0.9, 1.0, 0.9 ), 0.0, 0.2, 1.0, 0.0, 0.0 )
+                   , ( Color( 0.1, 0.2, 0.6 ), 0.0, 0.8, 0.0, 1.0, 1.0 ) ];
+
+    for ( i, ( base_color, metallic, roughness, transmission, clearcoat, sheen )) in settings.iter().enumerate() {
+
+        let mut material: Principled = Default::default();
+        material.base_color          = Arc::new( Solid_color::new( base_color ));
+        material.metallic            = Arc::new( Solid_color::scalar( *metallic ));
+        material.roughness           = Arc::new( Solid_color::scalar( *roughness ));
+        material.transmission        = Arc::new( Solid_color::scalar( *transmission ));
+        material.clearcoat           = Arc::new( Solid_color::scalar( *clearcoat ));
+        material.sheen               = Arc::new( Solid_color::scalar( *sheen ));
+
+        world.add( Arc::new( Sphere::new( Point3( -3.3 + 2.2 * i as f64, 1.0, 0.0 ), 1.0, Arc::new( material ))));
+    }
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 100;
+    cam.max_depth         = 50;
+
+    cam.vfov     = 30.0;
+    cam.lookfrom = Point3( 0.0, 3.0, 14.0 );
+    cam.lookat   = Point3( 0.0, 1.0, 0.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+fn thin_film_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    // Soap bubble, air on both sides of a water film.
+    let bubble = Dielectric::new( 1.0 ).with_film( Thin_film::constant( 300.0, 1.33 ));
+    world.add( Arc::new( Sphere::new( Point3( -2.2, 1.0, 0.0 ), 1.0, Arc::new( bubble ))));
+
+    // Oil slick over water.
+    let oily = Dielectric::new( 1.33 ).with_film( Thin_film::constant( 300.0, 1.47 ));
+    world.add( Arc::new( Sphere::new( Point3( 0.0, 1.0, 0.0 ), 1.0, Arc::new( oily ))));
+
+    // Oxide layer tinting a metal, like anodizing does.
+    let tinted = Conductor::silver( 0.15 ).with_film( Thin_film::constant( 150.0, 2.4 ));
+    world.add( Arc::new( Sphere::new( Point3( 2.2, 1.0, 0.0 ), 1.0, Arc::new( tinted ))));
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 100;
+    cam.max_depth         = 50;
+
+    cam.vfov     = 30.0;
+    cam.lookfrom = Point3( 0.0, 3.0, 12.0 );
+    cam.lookat   = Point3( 0.0, 1.0, 0.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+fn blackbody_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let material_ground = Lambertian::new( &Color( 0.5, 0.5, 0.5 ));
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    // Candle, tungsten, halogen, daylight and blue sky temperatures, all as bright.
+    for ( i, temperature ) in [ 1900.0, 2700.0, 3400.0, 6500.0, 10000.0 ].iter().enumerate() {
+        let glow = Diffuse_light::blackbody( *temperature, 1.0, true );
+        world.add( Arc::new( Sphere::new( Point3( -4.0 + 2.0 * i as f64, 0.6, 0.0 ), 0.6, Arc::new( glow ))));
+    }
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 100;
+    cam.max_depth         = 50;
+
+    cam.vfov     = 40.0;
+    cam.lookfrom = Point3( 0.0, 3.0, 10.0 );
+    cam.lookat   = Point3( 0.0, 0.6, 0.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.lights.push( Arc::new( Point_light::blackbody( Point3( 0.0, 6.0, 4.0 ), 2700.0, 20.0, true, 2.0 )));
+    cam.background = Arc::new( Solid_background::new( &Color( 0.0, 0.0, 0.0 )));
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+// MERL measured material, e.g. gold-metallic-paint.binary, next to the
+// analytic metal and microfacet conductor it's compared against.
+fn measured_brdf_render( path: &str ) {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    let measured = match Measured_brdf::load( path ) {
+        Ok( measured ) => measured,
+        Err( error )   => { eprintln!( "Can't load {}: {}", path, error ); return }
+    };
+
+    world.add( Arc::new( Sphere::new( Point3( -2.2, 1.0, 0.0 ), 1.0, Arc::new( Metal::new( &Color( 0.8, 0.6, 0.2 ), 0.3 )))));
+    world.add( Arc::new( Sphere::new( Point3(  0.0, 1.0, 0.0 ), 1.0, Arc::new( measured ))));
+    world.add( Arc::new( Sphere::new( Point3(  2.2, 1.0, 0.0 ), 1.0, Arc::new( Conductor::gold( 0.3 )))));
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 100;
+    cam.max_depth         = 50;
+
+    cam.vfov     = 30.0;
+    cam.lookfrom = Point3( 0.0, 3.0, 12.0 );
+    cam.lookat   = Point3( 0.0, 1.0, 0.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.lights.push( Arc::new( Point_light::new( Point3( 4.0, 6.0, 6.0 ), &Color( 60.0, 60.0, 60.0 ), 2.0 )));
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+// A row of spheres receding from a lens focused on the middle one, path being
+// a prescription in mm such as a 50mm double Gauss.
+fn lens_render( path: &str ) {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    for k in 0..7 {
+        let albedo = Color( 0.9 - 0.1 * k as f64, 0.2 + 0.1 * k as f64, 0.3 );
+        world.add( Arc::new( Sphere::new( Point3( -3.0 + 1.2 * k as f64, 0.5, -4.0 * k as f64 ), 0.5, Arc::new( Lambertian::new( &albedo )))));
+    }
+
+    let lens = match Lens_system::load( path ) {
+        Ok( lens )   => lens,
+        Err( error ) => { eprintln!( "Can't load {}: {}", path, error ); return }
+    };
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 3.0 / 2.0;
+    cam.image_width       = 600;
+    cam.samples_per_pixel = 200;
+    cam.max_depth         = 50;
+
+    cam.lookfrom = Point3( 0.0, 1.2, 6.0 );
+    cam.lookat   = Point3( 0.0, 0.5, -6.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.lens       = Some( lens );
+    cam.focus_dist = 18.0;
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+// Out of focus lights behind a sphere, shaped by the aperture and squeezed into
+// cat's eyes towards the corners.
+fn bokeh_render( aperture: Aperture ) {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    world.add( Arc::new( Sphere::new( Point3( 0.0, 0.0, 0.0 ), 1.0, Arc::new( Lambertian::new( &Color( 0.7, 0.3, 0.2 ))))));
+
+    let glow = Arc::new( Diffuse_light::blackbody( 3200.0, 20.0, true ));
+
+    for a in -3..=3 {
+        for b in -2..=2 {
+            let center = Point3( 8.0 * a as f64 + 2.0 * random_double(), 8.0 * b as f64 + 2.0 * random_double(), -40.0 );
+            world.add( Arc::new( Sphere::new( center, 0.3, glow.clone() )));
+        }
+    }
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 200;
+    cam.max_depth         = 20;
+
+    cam.vfov     = 40.0;
+    cam.lookfrom = Point3( 0.0, 0.0, 6.0 );
+    cam.lookat   = Point3( 0.0, 0.0, 0.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.defocus_angle = 6.0;
+    cam.focus_dist    = 6.0;
+    cam.aperture      = aperture;
+    cam.cat_eye       = 0.6;
+
+    cam.lights.push( Arc::new( Point_light::new( Point3( 3.0, 4.0, 6.0 ), &Color( 40.0, 40.0, 40.0 ), 2.0 )));
+    cam.background = Arc::new( Solid_background::new( &Color( 0.0, 0.0, 0.0 )));
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+// Miniature look: looking down on a field of spheres with the focus plane
+// tilted to stand steeper than the ground, leaving a thin band sharp.
+fn tilt_shift_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    for a in -10..10 {
+        for b in -20..4 {
+            let center = Point3( 1.5 * a as f64 + random_double(), 0.3, 1.5 * b as f64 + random_double() );
+            world.add( Arc::new( Sphere::new( center, 0.3, Arc::new( Lambertian::new( &( Color::random() * Color::random() ))))));
+        }
+    }
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 100;
+    cam.max_depth         = 20;
+
+    cam.vfov     = 40.0;
+    cam.lookfrom = Point3( 0.0, 8.0, 10.0 );
+    cam.lookat   = Point3( 0.0, 0.0, -4.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.defocus_angle = 2.0;
+    cam.focus_dist    = ( cam.lookat - cam.lookfrom ).norm();
+    cam.tilt          = -30.0;
+
+    cam.initializer();
+
+    let mut multithread_camera = Multithread_camera::new( cam );
+    multithread_camera.render_multithread( world );
+}
+
+// Turntable around the three big spheres, zooming in half way round, written
+// as frames/turntable_0000.ppm onwards.
+fn turntable_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    world.add( Arc::new( Sphere::new( Point3(  0.0, 1.0, 0.0 ), 1.0, Arc::new( Dielectric::new( 1.5 )))));
+    world.add( Arc::new( Sphere::new( Point3( -4.0, 1.0, 0.0 ), 1.0, Arc::new( Lambertian::new( &Color( 0.4, 0.2, 0.1 ))))));
+    world.add( Arc::new( Sphere::new( Point3(  4.0, 1.0, 0.0 ), 1.0, Arc::new( Metal::new( &Color( 0.7, 0.6, 0.5 ), 0.0 )))));
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 400;
+    cam.samples_per_pixel = 50;
+    cam.max_depth         = 20;
+
+    cam.vup = Vec3f( 0.0, 1.0, 0.0 );
+
+    let frames = 48;
+    let lookat = Point3( 0.0, 1.0, 0.0 );
+
+    let mut animation = Camera_animation::new( Interpolation::Catmull_rom );
+
+    for k in 0..=8 {
+        let angle    = 2.0 * PI * k as f64 / 8.0;
+        let radius   = if k == 4 { 8.0 } else { 13.0 };
+        let lookfrom = Point3( radius * f64::sin( angle ), 3.0, radius * f64::cos( angle ));
+
+        animation.add( Keyframe { frame: ( frames * k / 8 ) as f64
+                                , lookfrom
+                                , lookat
+                                , vfov: 30.0
+                                , focus_dist: ( lookat - lookfrom ).norm() } );
+    }
+
+    if let Err( error ) = animation.render_sequence( &cam, world, 0, frames - 1, "frames/turntable_" ) {
+        eprintln!( "Can't write frames: {}", error );
+    }
+}
+
+// The best image a minute gets, refining progressive.ppm as it goes.
+fn progressive_render() {
+
+    let world = Box::leak( Box::new(Hittable_list::new() ));
+
+    let checker = Arc::new( Checker_texture::new( 0.5, &Color( 0.2, 0.3, 0.1 ), &Color( 0.9, 0.9, 0.9 )));
+
+    let mut material_ground: Principled = Default::default();
+    material_ground.base_color          = checker;
+    world.add( Arc::new( Sphere::new( Point3( 0.0, -1000.0, 0.0 ), 1000.0, Arc::new( material_ground ))));
+
+    world.add( Arc::new( Sphere::new( Point3(  0.0, 1.0, 0.0 ), 1.0, Arc::new( Dielectric::new( 1.5 )))));
+    world.add( Arc::new( Sphere::new( Point3( -2.2, 1.0, 0.0 ), 1.0, Arc::new( Lambertian::new( &Color( 0.4, 0.2, 0.1 ))))));
+    world.add( Arc::new( Sphere::new( Point3(  2.2, 1.0, 0.0 ), 1.0, Arc::new( Metal::new( &Color( 0.7, 0.6, 0.5 ), 0.2 )))));
+
+    let mut cam: Camera = Default::default();
+
+    cam.aspect_ratio      = 16.0 / 9.0;
+    cam.image_width       = 800;
+    cam.samples_per_pixel = 4096;
+    cam.max_depth         = 50;
+
+    cam.vfov     = 30.0;
+    cam.lookfrom = Point3( 0.0, 2.0, 10.0 );
+    cam.lookat   = Point3( 0.0, 1.0, 0.0 );
+    cam.vup      = Vec3f( 0.0, 1.0, 0.0 );
+
+    cam.sampler           = Arc::new( Sobol_sampler );
+    cam.time_budget       = 60.0;
+    cam.progress_interval = 5.0;
+
+    cam.initializer();
+
+    if let Err( error ) = cam.render_progressive( world, "progressive.ppm" ) {
+        eprintln!( "Can't write progressive.ppm: {}", error );
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use crate::raytracer::math::interval::*;
+use crate::raytracer::math::vec3f::*;
+use crate::raytracer::ray::*;
+use crate::raytracer::hittable::*;
+use crate::raytracer::material::*;
+
+// Indexed triangles with one texture coordinate per position. Faces front
+// cross( p1 - p0, p2 - p0 ).
+pub struct Triangle_mesh {
+    positions: Vec<Point3>,
+    uvs: Vec<( f64, f64 )>,
+    indices: Vec<[ usize; 3 ]>,
+    dpdu: Vec<Vec3f>,
+    dpdv: Vec<Vec3f>,
+    mat: Arc<dyn Material + Send + Sync>,
+    cull_back: bool,
+}
+
+impl Triangle_mesh {
+
+    pub fn new( positions: Vec<Point3>, uvs: Vec<( f64, f64 )>, indices: Vec<[ usize; 3 ]>
+              , mat: Arc<dyn Material + Send + Sync> ) -> Triangle_mesh {
+
+        let ( dpdu, dpdv ) = Self::vertex_tangents( &positions, &uvs, &indices );
+
+        Triangle_mesh { positions, uvs, indices, dpdu, dpdv, mat, cull_back: false }
+    }
+
+    // Only hit from the front side.
+    pub fn cull_back_faces( self ) -> Triangle_mesh {
+        Triangle_mesh { cull_back: true, ..self }
+    }
+
+    // Averages the tangents of the faces around each vertex, so they vary
+    // smoothly across shared edges like the texture coordinates do.
+    fn vertex_tangents( positions: &[Point3], uvs: &[( f64, f64 )], indices: &[[ usize; 3 ]] ) -> ( Vec<Vec3f>, Vec<Vec3f> ) {
+
+        let mut dpdu  = vec![ Vec3f( 0.0, 0.0, 0.0 ); positions.len() ];
+        let mut dpdv  = vec![ Vec3f( 0.0, 0.0, 0.0 ); positions.len() ];
+        let mut faces = vec![ 0; positions.len() ];
+
+        for &[ i0, i1, i2 ] in indices {
+
+            let ( duv02, duv12 ) = (( uvs[i0].0 - uvs[i2].0, uvs[i0].1 - uvs[i2].1 )
+                                   , ( uvs[i1].0 - uvs[i2].0, uvs[i1].1 - uvs[i2].1 ));
+            let ( dp02, dp12 )   = ( positions[i0] - positions[i2], positions[i1] - positions[i2] );
+
+            // Degenerate texture coordinates say nothing about the tangents.
+            let det = duv02.0 * duv12.1 - duv02.1 * duv12.0;
+            if f64::abs( det ) < 1e-9 { continue; }
+
+            let face_dpdu = ( duv12.1 * dp02 - duv02.1 * dp12 ) / det;
+            let face_dpdv = ( duv02.0 * dp12 - duv12.0 * dp02 ) / det;
+
+            for i in [ i0, i1, i2 ] {
+                dpdu[i] += face_dpdu;
+                dpdv[i] += face_dpdv;
+                faces[i] += 1;
+            }
+        }
+
+        for i in 0..positions.len() {
+            if faces[i] > 0 {
+                dpdu[i] /= faces[i] as f64;
+                dpdv[i] /= faces[i] as f64;
+            }
+        }
+
+        ( dpdu, dpdv )
+    }
+
+    // Moller-Trumbore, returning t and the barycentrics of p1 and p2.
+    fn hit_triangle( &self, r: &Ray, ray_t: &Interval, face: &[ usize; 3 ] ) -> Option<( f64, f64, f64 )> {
+
+        let p0 = self.positions[face[0]];
+        let e1 = self.positions[face[1]] - p0;
+        let e2 = self.positions[face[2]] - p0;
+
+        let pvec = cross( r.direction(), e2 );
+        let det  = dot( e1, pvec );
+
+        // Parallel to the triangle, or seen from the back when culling.
+        if f64::abs( det ) < 1e-12 || ( self.cull_back && det < 0.0 ) {
+            return None
+        }
+
+        let tvec = r.origin() - p0;
+        let b1   = dot( tvec, pvec ) / det;
+        if !( 0.0..=1.0 ).contains( &b1 ) { return None }
+
+        let qvec = cross( tvec, e1 );
+        let b2   = dot( r.direction(), qvec ) / det;
+        if b2 < 0.0 || b1 + b2 > 1.0 { return None }
+
+        let t = dot( e2, qvec ) / det;
+        if !ray_t.surrounds( t ) { return None }
+
+        Some(( t, b1, b2 ))
+    }
+
+    fn record( &self, r: &Ray, &[ i0, i1, i2 ]: &[ usize; 3 ], t: f64, b1: f64, b2: f64 ) -> Hit_record {
+
+        let b0   = 1.0 - b1 - b2;
+        let lerp = | a: f64, b: f64, c: f64 | b0 * a + b1 * b + b2 * c;
+
+        let e1      = self.positions[i1] - self.positions[i0];
+        let e2      = self.positions[i2] - self.positions[i0];
+        let outward = cross( e1, e2 ).normalize();
+
+        let mut rec = Hit_record::new();
+
+        rec.t    = t;
+        rec.p    = r.at( t );
+        rec.u    = lerp( self.uvs[i0].0, self.uvs[i1].0, self.uvs[i2].0 );
+        rec.v    = lerp( self.uvs[i0].1, self.uvs[i1].1, self.uvs[i2].1 );
+        rec.dpdu = b0 * self.dpdu[i0] + b1 * self.dpdu[i1] + b2 * self.dpdu[i2];
+        rec.dpdv = b0 * self.dpdv[i0] + b1 * self.dpdv[i1] + b2 * self.dpdv[i2];
+
+        // Vertices whose faces all had degenerate texture coordinates.
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            rec.dpdu = e1;
+            rec.dpdv = e2;
+        }
+
+        rec.set_face_normal( r, &outward );
+
+        rec.mat = self.mat.clone();
+
+        rec
+    }
+}
+
+impl Hittable for Triangle_mesh {
+
+    fn hit( &self, r: &Ray, ray_t: &Interval ) -> ( bool, Hit_record ) {
+
+        let mut closest = Interval::new( ray_t.min, ray_t.max );
+        let mut found   = None;
+
+        for face in &self.indices {
+
+            let Some(( t, b1, b2 )) = self.hit_triangle( r, &closest, face ) else { continue };
+
+            let rec = self.record( r, face, t, b1, b2 );
+
+            // Farther faces may still show through the hole.
+            if rec.mat.is_cutout( &rec ) { continue; }
+
+            closest.max = t;
+            found       = Some( rec );
+        }
+
+        match found {
+            Some( rec ) => ( true, rec ),
+            None        => ( false, Hit_record::new() ),
+        }
+    }
+
+    fn hittable( &self ) { (); }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::raytracer::color::Color;
+    use crate::raytracer::texture::Solid_color;
+
+    // Unit square in the xy plane, texture coordinates following x and y.
+    fn square() -> Triangle_mesh {
+        square_of( Arc::new( Empty_mat {} ))
+    }
+
+    fn square_of( mat: Arc<dyn Material + Send + Sync> ) -> Triangle_mesh {
+        Triangle_mesh::new( vec![ Point3( 0.0, 0.0, 0.0 ), Point3( 1.0, 0.0, 0.0 ), Point3( 1.0, 1.0, 0.0 ), Point3( 0.0, 1.0, 0.0 ) ]
+                          , vec![ ( 0.0, 0.0 ), ( 1.0, 0.0 ), ( 1.0, 1.0 ), ( 0.0, 1.0 ) ]
+                          , vec![ [ 0, 1, 2 ], [ 0, 2, 3 ] ]
+                          , mat )
+    }
+
+    #[test]
+    fn tangents_follow_the_texture_coordinates() {
+
+        let ( hit, rec ) = square().hit( &Ray( Point3( 0.25, 0.6, 1.0 ), Vec3f( 0.0, 0.0, -1.0 )), &Interval::new( 0.001, f64::INFINITY ));
+
+        assert!( hit && rec.front_face );
+        assert!(( rec.t - 1.0 ).abs() < 1e-12 );
+        assert!(( rec.u - 0.25 ).abs() < 1e-12 && ( rec.v - 0.6 ).abs() < 1e-12 );
+        assert!(( rec.dpdu - Vec3f( 1.0, 0.0, 0.0 )).near_zero() );
+        assert!(( rec.dpdv - Vec3f( 0.0, 1.0, 0.0 )).near_zero() );
+        assert!(( rec.normal - Vec3f( 0.0, 0.0, 1.0 )).near_zero() );
+    }
+
+    #[test]
+    fn misses_outside_the_faces() {
+        let ( hit, _ ) = square().hit( &Ray( Point3( 1.5, 0.5, 1.0 ), Vec3f( 0.0, 0.0, -1.0 )), &Interval::new( 0.001, f64::INFINITY ));
+        assert!( !hit );
+    }
+
+    #[test]
+    fn culled_back_faces_are_missed() {
+
+        let above = Ray( Point3( 0.25, 0.6, 1.0 ), Vec3f( 0.0, 0.0, -1.0 ));
+        let below = Ray( Point3( 0.25, 0.6, -1.0 ), Vec3f( 0.0, 0.0, 1.0 ));
+        let t     = Interval::new( 0.001, f64::INFINITY );
+
+        assert!( square().hit( &below, &t ).0 );
+        assert!( square().cull_back_faces().hit( &above, &t ).0 );
+        assert!( !square().cull_back_faces().hit( &below, &t ).0 );
+    }
+
+    #[test]
+    fn cutouts_let_the_ray_through() {
+
+        let alpha  = Arc::new( Solid_color::new( &Color( 0.0, 0.0, 0.0 )));
+        let square = square_of( Arc::new( Alpha_mask::new( Arc::new( Empty_mat {} ), alpha, 0.5 )));
+
+        let ( hit, _ ) = square.hit( &Ray( Point3( 0.25, 0.6, 1.0 ), Vec3f( 0.0, 0.0, -1.0 )), &Interval::new( 0.001, f64::INFINITY ));
+        assert!( !hit );
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use crate::raytracer::math::interval::*;
+use crate::raytracer::math::vec3f::*;
+use crate::raytracer::ray::*;
+use crate::raytracer::hittable::*;
+use crate::raytracer::material::*;
+
+// Parallelogram with a corner at q and sides u and v. The texture coordinates
+// run along the sides, and the front faces cross( u, v ).
+pub struct Quad {
+    q: Point3,
+    u: Vec3f,
+    v: Vec3f,
+    w: Vec3f,
+    normal: Vec3f,
+    d: f64,
+    mat: Arc<dyn Material + Send + Sync>,
+    cull_back: bool,
+}
+
+impl Quad {
+
+    pub fn new( q: Point3, u: Vec3f, v: Vec3f, mat: Arc<dyn Material + Send + Sync> ) -> Quad {
+
+        let n      = cross( u, v );
+        let normal = n.normalize();
+        let d      = dot( normal, q );
+        let w      = n / n.norm_sq();
+
+        Quad { q, u, v, w, normal, d, mat, cull_back: false }
+    }
+
+    // Only hit from the front side.
+    pub fn cull_back_faces( self ) -> Quad {
+        Quad { cull_back: true, ..self }
+    }
+}
+
+impl Hittable for Quad {
+
+    fn hit( &self, r: &Ray, ray_t: &Interval ) -> ( bool, Hit_record ) {
+
+        let denom = dot( self.normal, r.direction() );
+
+        // Parallel to the plane, or seen from the back when culling.
+        if f64::abs( denom ) < 1e-8 || ( self.cull_back && denom > 0.0 ) {
+            return ( false, Hit_record::new() )
+        }
+
+        let t = ( self.d - dot( self.normal, r.origin() )) / denom;
+        if !ray_t.contains( t ) {
+            return ( false, Hit_record::new() )
+        }
+
+        let intersection = r.at( t );
+        let planar       = intersection - self.q;
+        let alpha        = dot( self.w, cross( planar, self.v ));
+        let beta         = dot( self.w, cross( self.u, planar ));
+
+        if !( 0.0..=1.0 ).contains( &alpha ) || !( 0.0..=1.0 ).contains( &beta ) {
+            return ( false, Hit_record::new() )
+        }
+
+        let mut rec = Hit_record::new();
+
+        rec.t    = t;
+        rec.p    = intersection;
+        rec.u    = alpha;
+        rec.v    = beta;
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
+
+        rec.set_face_normal( r, &self.normal );
+
+        rec.mat = self.mat.clone();
+
+        if rec.mat.is_cutout( &rec ) {
+            return ( false, Hit_record::new() )
+        }
+
+        ( true, rec )
+    }
+
+    fn hittable( &self ) { (); }
+}
@@ -5,5 +5,13 @@ pub mod ray;
 pub mod material;
 pub mod hittable;
 pub mod hittable_list;
+pub mod light;
+pub mod image;
+pub mod background;
+pub mod microfacet;
+pub mod texture;
+pub mod spectrum;
+pub mod sampler;
+pub mod filter;
 
 pub use std::rc::Rc;
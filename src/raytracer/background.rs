@@ -0,0 +1,289 @@
+use super::color::{ Color, luminance, xyz_to_rgb };
+use super::image::Image;
+use super::light::Directional_light;
+use super::math::PI;
+use super::math::distribution::Distribution_2d;
+use super::math::utilities::degrees_to_radians;
+use super::math::vec3f::*;
+use super::sampler::random_2d;
+
+pub trait Background: Send + Sync {
+
+    // Radiance coming from the unit direction dir.
+    fn value( &self, dir: Vec3f ) -> Color;
+
+    // Returns a unit direction, its radiance and its solid angle pdf.
+    // A zero pdf means the background can't be sampled directly.
+    fn sample( &self ) -> ( Vec3f, Color, f64 ) {
+        ( Vec3f( 0.0, 1.0, 0.0 ), Color( 0.0, 0.0, 0.0 ), 0.0 )
+    }
+
+    fn pdf( &self, dir: Vec3f ) -> f64 {
+        0.0
+    }
+}
+
+pub struct Solid_background {
+    color: Color,
+}
+
+impl Solid_background {
+    pub fn new( color: &Color ) -> Self {
+        Self { color: *color }
+    }
+}
+
+impl Background for Solid_background {
+    fn value( &self, dir: Vec3f ) -> Color {
+        self.color
+    }
+}
+
+pub struct Gradient_background {
+    bottom: Color,
+    top: Color,
+}
+
+impl Gradient_background {
+    pub fn new( bottom: &Color, top: &Color ) -> Self {
+        Self { bottom: *bottom, top: *top }
+    }
+}
+
+impl Default for Gradient_background {
+    fn default() -> Self {
+        Self::new( &Color( 1.0, 1.0, 1.0 ), &Color( 0.5, 0.7, 1.0 ))
+    }
+}
+
+impl Background for Gradient_background {
+    fn value( &self, dir: Vec3f ) -> Color {
+        let a = 0.5 * ( dir.y() + 1.0 );
+        ( 1.0 - a ) * self.bottom + a * self.top
+    }
+}
+
+// Equirectangular map, u = 0.5 looking down -z and v = 0 straight up.
+pub struct Environment_map {
+    image: Image,
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution_2d,
+}
+
+impl Environment_map {
+
+    // rotation turns the map around the y axis, in degrees.
+    pub fn new( image: Image, rotation: f64, intensity: f64 ) -> Self {
+
+        let ( width, height ) = ( image.width(), image.height() );
+
+        let mut func = Vec::with_capacity( width * height );
+        for y in 0..height {
+            let sin_theta = f64::sin( PI * ( y as f64 + 0.5 ) / height as f64 );
+            for x in 0..width {
+                func.push( luminance( &image.pixel( x, y )) * sin_theta );
+            }
+        }
+
+        let distribution = Distribution_2d::new( &func, width, height );
+
+        Self { image, rotation: degrees_to_radians( rotation ), intensity, distribution }
+    }
+
+    fn direction_to_uv( &self, dir: Vec3f ) -> ( f64, f64 ) {
+
+        let local = rotate_y( dir.normalize(), -self.rotation );
+        let theta = f64::acos( f64::clamp( local.y(), -1.0, 1.0 ));
+        let phi   = f64::atan2( local.x(), -local.z() );
+
+        ( 0.5 + phi / ( 2.0 * PI ), theta / PI )
+    }
+
+    fn uv_to_direction( &self, u: f64, v: f64 ) -> Vec3f {
+
+        let theta = v * PI;
+        let phi   = ( u - 0.5 ) * 2.0 * PI;
+        let local = Vec3f( f64::sin( theta ) * f64::sin( phi )
+                         , f64::cos( theta )
+                         , -f64::sin( theta ) * f64::cos( phi ));
+
+        rotate_y( local, self.rotation )
+    }
+
+    fn lookup( &self, u: f64, v: f64 ) -> Color {
+        let x = ( u * self.image.width() as f64 ) as usize;
+        let y = ( v * self.image.height() as f64 ) as usize;
+        self.intensity * self.image.pixel( x, y )
+    }
+}
+
+impl Background for Environment_map {
+
+    fn value( &self, dir: Vec3f ) -> Color {
+        let ( u, v ) = self.direction_to_uv( dir );
+        self.lookup( u, v )
+    }
+
+    fn sample( &self ) -> ( Vec3f, Color, f64 ) {
+
+        let ( u1, u2 )        = random_2d();
+        let ( u, v, map_pdf ) = self.distribution.sample_continuous( u1, u2 );
+        let sin_theta         = f64::sin( v * PI );
+        let dir               = self.uv_to_direction( u, v );
+
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return ( dir, Color( 0.0, 0.0, 0.0 ), 0.0 )
+        }
+
+        ( dir, self.lookup( u, v ), map_pdf / ( 2.0 * PI * PI * sin_theta ))
+    }
+
+    fn pdf( &self, dir: Vec3f ) -> f64 {
+
+        let ( u, v )  = self.direction_to_uv( dir );
+        let sin_theta = f64::sin( v * PI );
+
+        if sin_theta == 0.0 { 0.0 } else { self.distribution.pdf( u, v ) / ( 2.0 * PI * PI * sin_theta ) }
+    }
+}
+
+// Preetham et al. "A Practical Analytic Model for Daylight". Luminances are in
+// kcd/m^2 scaled by SKY_SCALE, the sun irradiance in the same units.
+pub struct Preetham_sky {
+    sun_direction: Vec3f,
+    perez_x: [ f64; 5 ],
+    perez_y: [ f64; 5 ],
+    perez_lum: [ f64; 5 ],
+    zenith: Vec3f,
+    sun_irradiance: Color,
+    ground: Color,
+}
+
+const SKY_SCALE: f64 = 0.1;
+
+impl Preetham_sky {
+
+    // sun_direction points from the ground towards the sun.
+    pub fn new( sun_direction: Vec3f, turbidity: f64, ground_albedo: &Color ) -> Self {
+
+        let sun_direction = sun_direction.normalize();
+        let sun_direction = Vec3f( sun_direction.x(), f64::max( sun_direction.y(), 0.001 ), sun_direction.z() ).normalize();
+
+        let t       = turbidity;
+        let theta_s = f64::acos( sun_direction.y() );
+
+        let perez_lum = [  0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251
+                        ,  0.1206 * t - 2.5771, -0.0670 * t + 0.3703 ];
+        let perez_x   = [ -0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125
+                        , -0.0641 * t - 0.8989, -0.0033 * t + 0.0452 ];
+        let perez_y   = [ -0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102
+                        , -0.0441 * t - 1.6537, -0.0109 * t + 0.0529 ];
+
+        let chi  = ( 4.0 / 9.0 - t / 120.0 ) * ( PI - 2.0 * theta_s );
+        let lum  = ( 4.0453 * t - 4.9710 ) * f64::tan( chi ) - 0.2155 * t + 2.4192;
+
+        let ( t1, t2, t3 ) = ( theta_s, theta_s * theta_s, theta_s * theta_s * theta_s );
+
+        let x = t * t * (  0.00166 * t3 - 0.00375 * t2 + 0.00209 * t1 )
+              + t     * ( -0.02903 * t3 + 0.06377 * t2 - 0.03202 * t1 + 0.00394 )
+              +         (  0.11693 * t3 - 0.21196 * t2 + 0.06052 * t1 + 0.25886 );
+        let y = t * t * (  0.00275 * t3 - 0.00610 * t2 + 0.00317 * t1 )
+              + t     * ( -0.04214 * t3 + 0.08970 * t2 - 0.04153 * t1 + 0.00516 )
+              +         (  0.15346 * t3 - 0.26756 * t2 + 0.06670 * t1 + 0.26688 );
+
+        let mut sky = Self { sun_direction
+                           , perez_x
+                           , perez_y
+                           , perez_lum
+                           , zenith: Vec3f( x, y, lum )
+                           , sun_irradiance: Self::sun_transmittance( theta_s, turbidity )
+                           , ground: Color( 0.0, 0.0, 0.0 ) };
+
+        sky.ground = ( 1.0 / PI ) * *ground_albedo * sky.horizontal_irradiance();
+        sky
+    }
+
+    // Matching sun, the real one is about 0.27 degrees of angular radius.
+    pub fn sun_light( &self, angular_radius: f64 ) -> Directional_light {
+        Directional_light::new( -self.sun_direction, &self.sun_irradiance, angular_radius )
+    }
+
+    fn perez( coeffs: &[ f64; 5 ], cos_theta: f64, gamma: f64 ) -> f64 {
+        ( 1.0 + coeffs[0] * f64::exp( coeffs[1] / cos_theta ))
+      * ( 1.0 + coeffs[2] * f64::exp( coeffs[3] * gamma ) + coeffs[4] * f64::cos( gamma ) * f64::cos( gamma ))
+    }
+
+    fn sky_radiance( &self, dir: Vec3f ) -> Color {
+
+        let cos_theta = f64::max( dir.y(), 0.001 );
+        let gamma     = f64::acos( f64::clamp( dot( dir, self.sun_direction ), -1.0, 1.0 ));
+        let theta_s   = f64::acos( self.sun_direction.y() );
+
+        let channel = |coeffs: &[ f64; 5 ], zenith: f64| {
+            zenith * Self::perez( coeffs, cos_theta, gamma ) / Self::perez( coeffs, 1.0, theta_s )
+        };
+
+        let x   = channel( &self.perez_x, self.zenith.0 );
+        let y   = channel( &self.perez_y, self.zenith.1 );
+        let lum = channel( &self.perez_lum, self.zenith.2 );
+
+        let xyz = Vec3f( x * lum / y, lum, ( 1.0 - x - y ) * lum / y );
+        let rgb = SKY_SCALE * xyz_to_rgb( &xyz );
+
+        Color( f64::max( rgb.0, 0.0 ), f64::max( rgb.1, 0.0 ), f64::max( rgb.2, 0.0 ))
+    }
+
+    // Rayleigh and aerosol extinction from the paper's appendix, evaluated at
+    // the wavelengths standing in for red, green and blue. Ozone is left out.
+    fn sun_transmittance( theta_s: f64, turbidity: f64 ) -> Color {
+
+        const SUN_ILLUMINANCE: f64 = 100.0;
+
+        let zenith_degrees = theta_s * 180.0 / PI;
+        let optical_mass   = 1.0 / ( f64::cos( theta_s ) + 0.15 * f64::powf( 93.885 - zenith_degrees, -1.253 ));
+        let beta           = 0.04608 * turbidity - 0.04586;
+
+        let transmittance = |lambda_um: f64| {
+            let rayleigh = f64::exp( -0.008735 * optical_mass * f64::powf( lambda_um, -4.08 ));
+            let aerosol  = f64::exp( -beta * optical_mass * f64::powf( lambda_um, -1.3 ));
+            rayleigh * aerosol
+        };
+
+        SKY_SCALE * SUN_ILLUMINANCE * Color( transmittance( 0.680 ), transmittance( 0.550 ), transmittance( 0.440 ))
+    }
+
+    // Sky plus sun irradiance on the ground, used to light the ground plane
+    // below the horizon.
+    fn horizontal_irradiance( &self ) -> Color {
+
+        let ( n_theta, n_phi ) = ( 32, 64 );
+        let d_theta            = 0.5 * PI / n_theta as f64;
+        let d_phi              = 2.0 * PI / n_phi as f64;
+
+        let mut irradiance = Color( 0.0, 0.0, 0.0 );
+
+        for i in 0..n_theta {
+            let theta = ( i as f64 + 0.5 ) * d_theta;
+            for j in 0..n_phi {
+                let phi = ( j as f64 + 0.5 ) * d_phi;
+                let dir = Vec3f( f64::sin( theta ) * f64::cos( phi ), f64::cos( theta ), f64::sin( theta ) * f64::sin( phi ));
+
+                irradiance += ( f64::cos( theta ) * f64::sin( theta ) * d_theta * d_phi ) * self.sky_radiance( dir );
+            }
+        }
+
+        irradiance + self.sun_direction.y() * self.sun_irradiance
+    }
+}
+
+impl Background for Preetham_sky {
+    fn value( &self, dir: Vec3f ) -> Color {
+        if dir.y() <= 0.0 { self.ground } else { self.sky_radiance( dir ) }
+    }
+}
+
+fn rotate_y( v: Vec3f, angle: f64 ) -> Vec3f {
+    let ( sin, cos ) = f64::sin_cos( angle );
+    Vec3f( cos * v.x() + sin * v.z(), v.y(), -sin * v.x() + cos * v.z() )
+}
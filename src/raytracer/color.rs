@@ -31,4 +31,38 @@ pub fn write_color( output: &mut impl std::io::Write
     writeln!( output, "{} {} {}", rbyte, gbyte, bbyte );
 }
 
-const INTENSITY: Interval = Interval { min: 0.0, max: 0.999 };
\ No newline at end of file
+// Plain PPM, or a PAM with an alpha channel when some pixels may stay uncovered.
+pub fn write_header( output: &mut impl std::io::Write
+                   , width: i32, height: i32, with_alpha: bool ) {
+
+    if with_alpha {
+        write!( output, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n", width, height );
+    } else {
+        writeln!( output, "P3\n{} {}\n255", width, height );
+    }
+}
+
+// Binary RGBA pixel of a PAM image, the color not premultiplied.
+pub fn write_color_alpha( output: &mut impl std::io::Write
+                        , pixel_color: &Color, alpha: f64 ) {
+
+    let byte = | c: f64 | ( 256.0 * INTENSITY.clamp( c )) as u8;
+
+    output.write_all( &[ byte( linear_to_gamma( pixel_color.x() ))
+                       , byte( linear_to_gamma( pixel_color.y() ))
+                       , byte( linear_to_gamma( pixel_color.z() ))
+                       , byte( alpha ) ] );
+}
+
+const INTENSITY: Interval = Interval { min: 0.0, max: 0.999 };
+
+pub fn luminance( color: &Color ) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+// CIE XYZ to linear sRGB, D65 white.
+pub fn xyz_to_rgb( xyz: &Vec3f ) -> Color {
+    Color(  3.2406 * xyz.0 - 1.5372 * xyz.1 - 0.4986 * xyz.2
+         , -0.9689 * xyz.0 + 1.8758 * xyz.1 + 0.0415 * xyz.2
+         ,  0.0557 * xyz.0 - 0.2040 * xyz.1 + 1.0570 * xyz.2 )
+}
\ No newline at end of file
@@ -0,0 +1,171 @@
+use super::math::PI;
+use super::math::distribution::Distribution_2d;
+
+// Pixel reconstruction filters, separable, with radius in pixels. Mitchell
+// and Lanczos have negative lobes, sharpening edges at the cost of some
+// ringing.
+#[derive( Clone, Copy, PartialEq )]
+pub enum Filter {
+    Box { radius: f64 },
+    Tent { radius: f64 },
+    // Shifted down to reach zero at the radius.
+    Gaussian { radius: f64, sigma: f64 },
+    // b = c = 1/3 is Mitchell and Netravali's recommendation.
+    Mitchell { radius: f64, b: f64, c: f64 },
+    // Sinc windowed by a sinc tau times wider.
+    Lanczos { radius: f64, tau: f64 },
+}
+
+impl Filter {
+
+    pub fn radius( &self ) -> f64 {
+        match *self {
+            Filter::Box { radius }          => radius,
+            Filter::Tent { radius }         => radius,
+            Filter::Gaussian { radius, .. } => radius,
+            Filter::Mitchell { radius, .. } => radius,
+            Filter::Lanczos { radius, .. }  => radius,
+        }
+    }
+
+    pub fn evaluate( &self, x: f64, y: f64 ) -> f64 {
+        self.evaluate_1d( x ) * self.evaluate_1d( y )
+    }
+
+    fn evaluate_1d( &self, x: f64 ) -> f64 {
+
+        let x = f64::abs( x );
+
+        if x > self.radius() {
+            return 0.0
+        }
+
+        match *self {
+
+            Filter::Box { .. } => 1.0,
+
+            Filter::Tent { radius } => radius - x,
+
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = | x: f64 | f64::exp( -x * x / ( 2.0 * sigma * sigma ));
+                f64::max( 0.0, gaussian( x ) - gaussian( radius ))
+            }
+
+            Filter::Mitchell { radius, b, c } => {
+                let x = 2.0 * x / radius;
+
+                if x > 1.0 {
+                    (( -b - 6.0 * c ) * x * x * x + ( 6.0 * b + 30.0 * c ) * x * x
+                    + ( -12.0 * b - 48.0 * c ) * x + ( 8.0 * b + 24.0 * c )) / 6.0
+                } else {
+                    (( 12.0 - 9.0 * b - 6.0 * c ) * x * x * x + ( -18.0 + 12.0 * b + 6.0 * c ) * x * x
+                    + ( 6.0 - 2.0 * b )) / 6.0
+                }
+            }
+
+            Filter::Lanczos { tau, .. } => sinc( x ) * sinc( x / tau ),
+        }
+    }
+}
+
+fn sinc( x: f64 ) -> f64 {
+    if x < 1e-5 { 1.0 } else { f64::sin( PI * x ) / ( PI * x ) }
+}
+
+// Filter importance sampling: offsets are drawn in proportion to the filter's
+// magnitude over a table, as in pbrt's FilterSampler, leaving each sample a
+// weight of just its sign. Pixels then take the weighted average of their
+// samples.
+pub struct Filter_sampler {
+    radius: f64,
+    values: Vec<f64>,
+    distribution: Distribution_2d,
+}
+
+const TABLE_SIZE: usize = 64;
+
+impl Filter_sampler {
+
+    pub fn new( filter: Filter ) -> Self {
+
+        let radius = filter.radius();
+
+        let values: Vec<f64> = ( 0..TABLE_SIZE * TABLE_SIZE ).map( |k| {
+            let x = ( 2.0 * (( k % TABLE_SIZE ) as f64 + 0.5 ) / TABLE_SIZE as f64 - 1.0 ) * radius;
+            let y = ( 2.0 * (( k / TABLE_SIZE ) as f64 + 0.5 ) / TABLE_SIZE as f64 - 1.0 ) * radius;
+            filter.evaluate( x, y )
+        }).collect();
+
+        let distribution = Distribution_2d::new( &values, TABLE_SIZE, TABLE_SIZE );
+
+        Filter_sampler { radius, values, distribution }
+    }
+
+    // Offset from the pixel's center, and its weight.
+    pub fn sample( &self, u: f64, v: f64 ) -> ( f64, f64, f64 ) {
+
+        let ( x, y, _ ) = self.distribution.sample_continuous( u, v );
+
+        let cx = usize::min(( x * TABLE_SIZE as f64 ) as usize, TABLE_SIZE - 1 );
+        let cy = usize::min(( y * TABLE_SIZE as f64 ) as usize, TABLE_SIZE - 1 );
+
+        let weight = if self.values[cy * TABLE_SIZE + cx] < 0.0 { -1.0 } else { 1.0 };
+
+        ( ( 2.0 * x - 1.0 ) * self.radius, ( 2.0 * y - 1.0 ) * self.radius, weight )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const EPSILON: f64 = 1e-12;
+
+    #[test]
+    fn box_is_flat_within_its_radius() {
+        let filter = Filter::Box { radius: 0.5 };
+        assert_eq!( filter.evaluate( 0.4, -0.3 ), 1.0 );
+        assert_eq!( filter.evaluate( 0.6, 0.0 ), 0.0 );
+    }
+
+    #[test]
+    fn tent_is_separable_and_falls_to_zero() {
+        let filter = Filter::Tent { radius: 2.0 };
+        assert!(( filter.evaluate( 0.0, 0.0 ) - 4.0 ).abs() < EPSILON );
+        assert!(( filter.evaluate( 1.0, -0.5 ) - 1.0 * 1.5 ).abs() < EPSILON );
+        assert!( filter.evaluate( 2.0, 0.0 ).abs() < EPSILON );
+    }
+
+    #[test]
+    fn gaussian_reaches_zero_at_its_radius() {
+        let filter = Filter::Gaussian { radius: 1.5, sigma: 0.5 };
+        assert!( filter.evaluate( 0.0, 0.0 ) > 0.0 );
+        assert!( filter.evaluate( 1.5, 0.0 ).abs() < EPSILON );
+        assert!( filter.evaluate( 0.3, 0.2 ) > filter.evaluate( 0.6, 0.2 ));
+    }
+
+    #[test]
+    fn mitchell_has_a_negative_lobe() {
+
+        let filter = Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 };
+
+        assert!(( filter.evaluate( 0.0, 0.0 ) - f64::powi( 8.0 / 9.0, 2 )).abs() < EPSILON );
+        assert!( filter.evaluate( 1.5, 0.0 ) < 0.0 );
+        assert!( filter.evaluate( 2.0, 0.0 ).abs() < EPSILON );
+    }
+
+    #[test]
+    fn lanczos_is_one_at_the_center_and_zero_at_integers() {
+        let filter = Filter::Lanczos { radius: 3.0, tau: 3.0 };
+        assert!(( filter.evaluate( 0.0, 0.0 ) - 1.0 ).abs() < EPSILON );
+        assert!( filter.evaluate( 1.0, 0.0 ).abs() < EPSILON );
+        assert!( filter.evaluate( 0.0, 2.0 ).abs() < EPSILON );
+    }
+
+    #[test]
+    fn filters_are_symmetric() {
+        let filter = Filter::Mitchell { radius: 2.0, b: 1.0 / 3.0, c: 1.0 / 3.0 };
+        assert_eq!( filter.evaluate( 0.7, -1.2 ), filter.evaluate( -0.7, 1.2 ));
+    }
+}
@@ -8,10 +8,22 @@ use std::sync::Arc;
 use super::Rc;
 use super::material;
 
+// How far spawned rays start off the surface.
+const SPAWN_OFFSET: f64 = 1e-4;
+
+// normal is the shading normal materials use, possibly bent by a normal map,
+// while geometric_normal is the true surface's. Both face the incoming ray.
+// dpdu and dpdv are the surface's tangents along the texture coordinates.
+#[derive( Clone )]
 pub struct Hit_record {
     pub p: Point3,
     pub normal: Vec3f,
+    pub geometric_normal: Vec3f,
+    pub dpdu: Vec3f,
+    pub dpdv: Vec3f,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
     pub mat: Arc<dyn material::Material + Send + Sync>,
 }
@@ -22,7 +34,12 @@ impl Hit_record {
         Self {
             p: Point3( 0.0, 0.0, 0.0 ),
             normal: Vec3f( 0.0, 0.0, 0.0 ),
+            geometric_normal: Vec3f( 0.0, 0.0, 0.0 ),
+            dpdu: Vec3f( 0.0, 0.0, 0.0 ),
+            dpdv: Vec3f( 0.0, 0.0, 0.0 ),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
             front_face: false,
             mat: Arc::new( material::Empty_mat {} ),
         }
@@ -31,6 +48,27 @@ impl Hit_record {
     pub fn set_face_normal( &mut self, r: &Ray, outward_normal: &Vec3f ) {
         self.front_face = dot( r.direction(), *outward_normal ) < 0.0;
         self.normal     = if self.front_face { *outward_normal } else { -*outward_normal };
+
+        self.geometric_normal = self.normal;
+    }
+
+    // The shading normal turned back to the outward side, for perturbing it in
+    // the tangents' frame.
+    pub fn outward_normal( &self ) -> Vec3f {
+        if self.front_face { self.normal } else { -self.normal }
+    }
+
+    // Whether w lies on the same side of the shading and the true surface.
+    // Directions in between would leak light through the geometry.
+    pub fn same_side( &self, w: Vec3f ) -> bool {
+        dot( w, self.normal ) * dot( w, self.geometric_normal ) > 0.0
+    }
+
+    // Origin for a ray leaving towards w, pushed off the true surface on w's
+    // side so it doesn't hit it again.
+    pub fn spawn_point( &self, w: Vec3f ) -> Point3 {
+        let offset = if dot( w, self.geometric_normal ) > 0.0 { SPAWN_OFFSET } else { -SPAWN_OFFSET };
+        self.p + offset * self.geometric_normal
     }
 }
 
@@ -0,0 +1,364 @@
+use std::fs;
+use std::io;
+
+use super::color::Color;
+
+// Linear floating point image, row 0 being the top of the picture.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+
+    pub fn new( width: usize, height: usize, pixels: Vec<Color> ) -> Self {
+        Self { width, height, pixels }
+    }
+
+    // Picks the decoder from the file extension.
+    pub fn load( path: &str ) -> io::Result<Image> {
+
+        let bytes = fs::read( path )?;
+        let lower = path.to_lowercase();
+
+        let image =
+            if lower.ends_with( ".hdr" ) {
+                Self::decode_hdr( &bytes )?
+            } else if lower.ends_with( ".pfm" ) {
+                Self::decode_pfm( &bytes )?
+            } else if lower.ends_with( ".ppm" ) {
+                Self::decode_ppm( &bytes )?
+            } else {
+                return Err( invalid_data( "unsupported image format" ))
+            };
+
+        // Nothing to look up or sample in.
+        if image.width == 0 || image.height == 0 {
+            return Err( invalid_data( "empty image" ))
+        }
+
+        Ok( image )
+    }
+
+    pub fn width( &self ) -> usize { self.width }
+    pub fn height( &self ) -> usize { self.height }
+
+    pub fn pixel( &self, x: usize, y: usize ) -> Color {
+        let x = usize::min( x, self.width - 1 );
+        let y = usize::min( y, self.height - 1 );
+        self.pixels[y * self.width + x]
+    }
+
+    // Radiance RGBE, flat or with the new run length encoding.
+    fn decode_hdr( bytes: &[u8] ) -> io::Result<Image> {
+
+        let mut pos = 0;
+
+        let magic = read_line( bytes, &mut pos )?;
+        if !magic.starts_with( "#?" ) {
+            return Err( invalid_data( "not a radiance file" ))
+        }
+
+        loop {
+            let line = read_line( bytes, &mut pos )?;
+            if line.is_empty() { break; }
+            if line.starts_with( "FORMAT=" ) && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err( invalid_data( "only 32-bit_rle_rgbe radiance files are supported" ))
+            }
+        }
+
+        let resolution = read_line( bytes, &mut pos )?;
+        let tokens: Vec<&str> = resolution.split_whitespace().collect();
+        if tokens.len() != 4 || tokens[2] != "+X" || ( tokens[0] != "-Y" && tokens[0] != "+Y" ) {
+            return Err( invalid_data( "unsupported radiance orientation" ))
+        }
+
+        let height = tokens[1].parse::<usize>().map_err( |_| invalid_data( "bad height" ))?;
+        let width  = tokens[3].parse::<usize>().map_err( |_| invalid_data( "bad width" ))?;
+
+        let mut pixels   = Vec::with_capacity( width * height );
+        let mut scanline = vec![ [ 0u8; 4 ]; width ];
+
+        for _ in 0..height {
+            read_hdr_scanline( bytes, &mut pos, &mut scanline )?;
+            pixels.extend( scanline.iter().map( rgbe_to_color ));
+        }
+
+        if tokens[0] == "+Y" {
+            pixels = flip_rows( pixels, width, height );
+        }
+
+        Ok( Image { width, height, pixels } )
+    }
+
+    // Portable float map, both the color (PF) and the grayscale (Pf) kinds.
+    fn decode_pfm( bytes: &[u8] ) -> io::Result<Image> {
+
+        let mut pos = 0;
+
+        let kind   = read_token( bytes, &mut pos )?;
+        let width  = read_token( bytes, &mut pos )?.parse::<usize>().map_err( |_| invalid_data( "bad width" ))?;
+        let height = read_token( bytes, &mut pos )?.parse::<usize>().map_err( |_| invalid_data( "bad height" ))?;
+        let scale  = read_token( bytes, &mut pos )?.parse::<f64>().map_err( |_| invalid_data( "bad scale" ))?;
+        pos += 1;
+
+        let channels = match kind.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _    => return Err( invalid_data( "not a pfm file" )),
+        };
+
+        let little_endian = scale < 0.0;
+        let count         = width * height * channels;
+
+        if bytes.len() < pos + count * 4 {
+            return Err( invalid_data( "truncated pfm file" ))
+        }
+
+        let floats: Vec<f64> = bytes[pos..pos + count * 4].chunks_exact( 4 ).map( |b| {
+            let b = [ b[0], b[1], b[2], b[3] ];
+            if little_endian { f32::from_le_bytes( b ) as f64 } else { f32::from_be_bytes( b ) as f64 }
+        }).collect();
+
+        let pixels: Vec<Color> = floats.chunks_exact( channels ).map( |c| {
+            if channels == 3 { Color( c[0], c[1], c[2] ) } else { Color( c[0], c[0], c[0] ) }
+        }).collect();
+
+        // Rows are stored bottom to top.
+        Ok( Image { width, height, pixels: flip_rows( pixels, width, height ) } )
+    }
+
+    // Plain (P3) and binary (P6) pixmaps, undoing the gamma 2 write_color applies.
+    fn decode_ppm( bytes: &[u8] ) -> io::Result<Image> {
+
+        let mut pos = 0;
+
+        let kind   = read_token( bytes, &mut pos )?;
+        let width  = read_token( bytes, &mut pos )?.parse::<usize>().map_err( |_| invalid_data( "bad width" ))?;
+        let height = read_token( bytes, &mut pos )?.parse::<usize>().map_err( |_| invalid_data( "bad height" ))?;
+        let maxval = read_token( bytes, &mut pos )?.parse::<usize>().map_err( |_| invalid_data( "bad maxval" ))?;
+
+        let count = width * height * 3;
+
+        let values: Vec<usize> = match kind.as_str() {
+            "P3" => {
+                let mut values = Vec::with_capacity( count );
+                for _ in 0..count {
+                    values.push( read_token( bytes, &mut pos )?.parse::<usize>().map_err( |_| invalid_data( "bad sample" ))? );
+                }
+                values
+            }
+            "P6" => {
+                pos += 1;
+                let size = if maxval < 256 { 1 } else { 2 };
+                if bytes.len() < pos + count * size {
+                    return Err( invalid_data( "truncated ppm file" ))
+                }
+                bytes[pos..pos + count * size].chunks_exact( size ).map( |b| {
+                    if size == 1 { b[0] as usize } else { ( b[0] as usize ) << 8 | b[1] as usize }
+                }).collect()
+            }
+            _ => return Err( invalid_data( "not a ppm file" )),
+        };
+
+        let linear = | value: usize | {
+            let gamma = value as f64 / maxval as f64;
+            gamma * gamma
+        };
+
+        let pixels = values.chunks_exact( 3 ).map( |c| Color( linear( c[0] ), linear( c[1] ), linear( c[2] ))).collect();
+
+        Ok( Image { width, height, pixels } )
+    }
+}
+
+fn invalid_data( message: &str ) -> io::Error {
+    io::Error::new( io::ErrorKind::InvalidData, message.to_string() )
+}
+
+fn read_byte( bytes: &[u8], pos: &mut usize ) -> io::Result<u8> {
+
+    if *pos >= bytes.len() {
+        return Err( io::Error::new( io::ErrorKind::UnexpectedEof, "unexpected end of image" ))
+    }
+
+    *pos += 1;
+    Ok( bytes[*pos - 1] )
+}
+
+fn read_line( bytes: &[u8], pos: &mut usize ) -> io::Result<String> {
+
+    let mut line = Vec::new();
+
+    loop {
+        let byte = read_byte( bytes, pos )?;
+        if byte == b'\n' { break; }
+        line.push( byte );
+    }
+
+    Ok( String::from_utf8_lossy( &line ).trim_end().to_string() )
+}
+
+fn read_token( bytes: &[u8], pos: &mut usize ) -> io::Result<String> {
+
+    while *pos < bytes.len() && bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+
+    let start = *pos;
+    while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+
+    if start == *pos {
+        return Err( io::Error::new( io::ErrorKind::UnexpectedEof, "unexpected end of image" ))
+    }
+
+    Ok( String::from_utf8_lossy( &bytes[start..*pos] ).to_string() )
+}
+
+fn read_hdr_scanline( bytes: &[u8], pos: &mut usize, scanline: &mut [ [ u8; 4 ] ] ) -> io::Result<()> {
+
+    let width = scanline.len();
+
+    let mut first = [ 0u8; 4 ];
+    for c in first.iter_mut() { *c = read_byte( bytes, pos )?; }
+
+    let is_rle = ( 8..0x8000 ).contains( &width )
+              && first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+
+    if !is_rle {
+        scanline[0] = first;
+        for pixel in scanline.iter_mut().skip( 1 ) {
+            for c in pixel.iter_mut() { *c = read_byte( bytes, pos )?; }
+        }
+        return Ok(())
+    }
+
+    if (( first[2] as usize ) << 8 | first[3] as usize ) != width {
+        return Err( invalid_data( "wrong radiance scanline width" ))
+    }
+
+    for channel in 0..4 {
+
+        let mut x = 0;
+        while x < width {
+
+            let count = read_byte( bytes, pos )? as usize;
+
+            if count > 128 {
+                let run   = count - 128;
+                let value = read_byte( bytes, pos )?;
+                if x + run > width { return Err( invalid_data( "bad radiance run length" )) }
+                for pixel in &mut scanline[x..x + run] { pixel[channel] = value; }
+                x += run;
+            } else {
+                if count == 0 || x + count > width { return Err( invalid_data( "bad radiance run length" )) }
+                for pixel in &mut scanline[x..x + count] { pixel[channel] = read_byte( bytes, pos )?; }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn rgbe_to_color( rgbe: &[ u8; 4 ] ) -> Color {
+
+    if rgbe[3] == 0 {
+        return Color( 0.0, 0.0, 0.0 )
+    }
+
+    let f = f64::powi( 2.0, rgbe[3] as i32 - ( 128 + 8 ));
+    Color( rgbe[0] as f64 * f, rgbe[1] as f64 * f, rgbe[2] as f64 * f )
+}
+
+fn flip_rows( pixels: Vec<Color>, width: usize, height: usize ) -> Vec<Color> {
+    ( 0..height ).rev().flat_map( |y| pixels[y * width..( y + 1 ) * width].to_vec() ).collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn write_temp( name: &str, bytes: &[u8] ) -> String {
+        let path = std::env::temp_dir().join( format!( "{}_{}", std::process::id(), name ));
+        fs::write( &path, bytes ).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn decodes_run_length_encoded_hdr() {
+
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        bytes.extend_from_slice( &[ 2, 2, 0, 8 ] );
+        bytes.extend_from_slice( &[ 128 + 8, 128 ] );
+        bytes.extend_from_slice( &[ 8, 0, 16, 32, 48, 64, 80, 96, 112 ] );
+        bytes.extend_from_slice( &[ 128 + 8, 0 ] );
+        bytes.extend_from_slice( &[ 128 + 8, 129 ] );
+
+        let image = Image::decode_hdr( &bytes ).unwrap();
+        assert_eq!(( image.width(), image.height() ), ( 8, 1 ));
+
+        for x in 0..8 {
+            let c = image.pixel( x, 0 );
+            assert_eq!( c.x(), 1.0 );
+            assert_eq!( c.y(), x as f64 * 16.0 / 128.0 );
+            assert_eq!( c.z(), 0.0 );
+        }
+    }
+
+    #[test]
+    fn decodes_pfm_bottom_to_top() {
+
+        let mut bytes = b"PF\n1 2\n-1.0\n".to_vec();
+        for f in [ 1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0 ] {
+            bytes.extend_from_slice( &f.to_le_bytes() );
+        }
+
+        let image = Image::decode_pfm( &bytes ).unwrap();
+        let top   = image.pixel( 0, 0 );
+        let down  = image.pixel( 0, 1 );
+
+        assert_eq!(( top.x(), top.y(), top.z() ), ( 4.0, 5.0, 6.0 ));
+        assert_eq!(( down.x(), down.y(), down.z() ), ( 1.0, 2.0, 3.0 ));
+    }
+
+    #[test]
+    fn rejects_truncated_pfm() {
+        assert!( Image::decode_pfm( b"PF\n2 2\n-1.0\n\0\0\0\0" ).is_err() );
+    }
+
+    #[test]
+    fn decodes_ascii_and_binary_ppm() {
+
+        let ascii  = Image::decode_ppm( b"P3\n2 1\n255\n255 0 0  0 51 255\n" );
+        let binary = Image::decode_ppm( b"P6 2 1 255\n\xff\x00\x00\x00\x33\xff" );
+
+        for image in [ ascii.unwrap(), binary.unwrap() ] {
+
+            let ( left, right ) = ( image.pixel( 0, 0 ), image.pixel( 1, 0 ));
+
+            assert_eq!(( left.x(), left.y(), left.z() ), ( 1.0, 0.0, 0.0 ));
+            assert!(( right.y() - 0.04 ).abs() < 1e-12 && right.z() == 1.0 );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_ppm() {
+        assert!( Image::decode_ppm( b"P6 2 2 255\n\x00\x00\x00" ).is_err() );
+        assert!( Image::decode_ppm( b"P3 1 1 255\n0 0" ).is_err() );
+    }
+
+    #[test]
+    fn load_rejects_empty_image() {
+
+        let path  = write_temp( "empty.pfm", b"PF\n0 0\n-1.0\n" );
+        let error = Image::load( &path ).err().unwrap();
+        fs::remove_file( &path ).unwrap();
+
+        assert_eq!( error.kind(), io::ErrorKind::InvalidData );
+    }
+}
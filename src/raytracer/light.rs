@@ -0,0 +1,148 @@
+use super::color::Color;
+use super::math::INFINITY;
+use super::math::PI;
+use super::math::onb::Onb;
+use super::math::utilities::degrees_to_radians;
+use super::math::vec3f::*;
+use super::sampler::random_2d;
+use super::spectrum::Emission;
+
+pub trait Light: Send + Sync {
+
+    // Returns the unit direction from p towards the light, the incoming
+    // radiance arriving at p and the distance to the light. The radiance is
+    // at the hero wavelength lambda's wavelengths, or RGB when it's 0.
+    fn sample_li( &self, p: Point3, lambda: f64 ) -> ( Vec3f, Color, f64 );
+}
+
+pub struct Point_light {
+    position: Point3,
+    intensity: Emission,
+    falloff: f64,
+}
+
+impl Point_light {
+
+    // falloff is the distance exponent, 2.0 being the physical inverse square law.
+    pub fn new( position: Point3, intensity: &Color, falloff: f64 ) -> Self {
+        Self { position, intensity: Emission::Rgb( *intensity ), falloff }
+    }
+
+    // Glowing like a blackbody at temperature Kelvin, see blackbody_rgb.
+    pub fn blackbody( position: Point3, temperature: f64, intensity: f64, normalize: bool, falloff: f64 ) -> Self {
+        Self { position, intensity: Emission::blackbody( temperature, intensity, normalize ), falloff }
+    }
+}
+
+impl Light for Point_light {
+
+    fn sample_li( &self, p: Point3, lambda: f64 ) -> ( Vec3f, Color, f64 ) {
+
+        let to_light = self.position - p;
+        let distance = to_light.norm();
+        let li       = self.intensity.at( lambda ) / f64::powf( distance, self.falloff );
+
+        ( to_light / distance, li, distance )
+    }
+}
+
+pub struct Spot_light {
+    position: Point3,
+    direction: Vec3f,
+    intensity: Emission,
+    falloff: f64,
+    cos_cone: f64,
+    cos_falloff_start: f64,
+}
+
+impl Spot_light {
+
+    // cone_angle is the half angle of the whole cone and falloff_angle the half
+    // angle where the edge starts to fade out, both in degrees.
+    pub fn new( position: Point3, lookat: Point3, intensity: &Color, falloff: f64
+              , cone_angle: f64, falloff_angle: f64 ) -> Self {
+
+        let falloff_angle = f64::min( falloff_angle, cone_angle );
+
+        Self { position
+             , direction: ( lookat - position ).normalize()
+             , intensity: Emission::Rgb( *intensity )
+             , falloff
+             , cos_cone: f64::cos( degrees_to_radians( cone_angle ))
+             , cos_falloff_start: f64::cos( degrees_to_radians( falloff_angle )) }
+    }
+
+    // Glowing like a blackbody at temperature Kelvin, see blackbody_rgb.
+    pub fn blackbody( position: Point3, lookat: Point3, temperature: f64, intensity: f64, normalize: bool
+                    , falloff: f64, cone_angle: f64, falloff_angle: f64 ) -> Self {
+        Self { intensity: Emission::blackbody( temperature, intensity, normalize )
+             , ..Self::new( position, lookat, &Color( 0.0, 0.0, 0.0 ), falloff, cone_angle, falloff_angle ) }
+    }
+
+    fn smoothstep( edge0: f64, edge1: f64, x: f64 ) -> f64 {
+
+        if edge0 == edge1 {
+            return if x < edge0 { 0.0 } else { 1.0 }
+        }
+
+        let t = f64::clamp(( x - edge0 ) / ( edge1 - edge0 ), 0.0, 1.0 );
+        t * t * ( 3.0 - 2.0 * t )
+    }
+}
+
+impl Light for Spot_light {
+
+    fn sample_li( &self, p: Point3, lambda: f64 ) -> ( Vec3f, Color, f64 ) {
+
+        let to_light  = self.position - p;
+        let distance  = to_light.norm();
+        let wi        = to_light / distance;
+        let cos_theta = dot( -wi, self.direction );
+        let edge      = Self::smoothstep( self.cos_cone, self.cos_falloff_start, cos_theta );
+        let li        = edge * self.intensity.at( lambda ) / f64::powf( distance, self.falloff );
+
+        ( wi, li, distance )
+    }
+}
+
+pub struct Directional_light {
+    basis: Onb,
+    irradiance: Emission,
+    cos_radius: f64,
+}
+
+impl Directional_light {
+
+    // direction is where the light travels to, angular_radius (in degrees) gives
+    // the light a disk-like size in the sky for soft shadows.
+    pub fn new( direction: Vec3f, irradiance: &Color, angular_radius: f64 ) -> Self {
+        Self { basis: Onb::new( -direction )
+             , irradiance: Emission::Rgb( *irradiance )
+             , cos_radius: f64::cos( degrees_to_radians( angular_radius )) }
+    }
+
+    // Shining like a blackbody at temperature Kelvin, see blackbody_rgb.
+    pub fn blackbody( direction: Vec3f, temperature: f64, irradiance: f64, normalize: bool, angular_radius: f64 ) -> Self {
+        Self { irradiance: Emission::blackbody( temperature, irradiance, normalize )
+             , ..Self::new( direction, &Color( 0.0, 0.0, 0.0 ), angular_radius ) }
+    }
+}
+
+impl Light for Directional_light {
+
+    fn sample_li( &self, p: Point3, lambda: f64 ) -> ( Vec3f, Color, f64 ) {
+
+        if self.cos_radius >= 1.0 {
+            return ( self.basis.w(), self.irradiance.at( lambda ), INFINITY )
+        }
+
+        let ( u1, u2 ) = random_2d();
+
+        let cos_theta = 1.0 - u1 * ( 1.0 - self.cos_radius );
+        let sin_theta = f64::sqrt( 1.0 - cos_theta * cos_theta );
+        let phi       = 2.0 * PI * u2;
+        let local     = Vec3f( f64::cos( phi ) * sin_theta, f64::sin( phi ) * sin_theta, cos_theta );
+
+        ( self.basis.transform( local ), self.irradiance.at( lambda ), INFINITY )
+    }
+}
@@ -1,12 +1,67 @@
 
-use super::color::Color;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use super::color::{ Color, luminance };
 use super::hittable::Hit_record;
-use super::math::utilities::random_double;
-use super::math::vec3f::{ Vec3f, dot };
-use super::ray::Ray;
+use super::math::PI;
+use super::math::onb::Onb;
+use super::math::utilities::{ degrees_to_radians
+                            , random_double };
+use super::math::vec3f::{ Vec3f, Point3, cross, dot };
+use super::microfacet::{ Ggx
+                       , fresnel_conductor
+                       , fresnel_conductor_rgb
+                       , fresnel_thin_film
+                       , fresnel_dielectric
+                       , refract_through
+                       , dielectric_eval
+                       , dielectric_pdf };
+use super::ray::{ Ray, Medium };
+use super::sampler::random_2d;
+use super::spectrum::{ Ior
+                     , Emission
+                     , at_hero_wavelengths
+                     , at_wavelength
+                     , reflectance_to_rgb
+                     , rgb_at_wavelength };
+use super::texture::{ Texture, Solid_color };
 
+// Colors scatter(), eval() and emitted() return are RGB for RGB rays, and for
+// spectral ones their values at the ray's hero wavelengths.
 pub trait Material {
     fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray );
+
+    // BRDF times the cosine towards the unit direction wi, used when sampling
+    // lights directly. Specular materials can't be hit by it, so zero by default.
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+        Color( 0.0, 0.0, 0.0 )
+    }
+
+    // Solid angle pdf of scatter() picking wi, zero when it's a delta distribution.
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        0.0
+    }
+
+    // Radiance the surface emits towards the incoming ray.
+    fn emitted( &self, r_in: &Ray, rec: &Hit_record ) -> Color {
+        Color( 0.0, 0.0, 0.0 )
+    }
+
+    // Whether the hit falls in a hole of an alpha mask, so that primitives
+    // ignore it and the ray goes on through.
+    fn is_cutout( &self, rec: &Hit_record ) -> bool {
+        false
+    }
+
+    // scatter() plus the pdf the direction was picked with. Materials mixing
+    // specular and glossy lobes override it to report zero for specular picks.
+    fn scatter_with_pdf( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray, f64 ) {
+        let ( bool_scat, attenuation, scattered ) = self.scatter( r_in, rec );
+        let pdf = self.scattering_pdf( r_in, rec, scattered.direction().normalize() );
+        ( bool_scat, attenuation, scattered, pdf )
+    }
 }
 
 pub struct Empty_mat {}
@@ -17,6 +72,40 @@ impl Material for Empty_mat {
     }
 }
 
+// Emitter that doesn't reflect anything, glowing the same in every direction.
+pub struct Diffuse_light {
+    emit: Emission,
+    one_sided: bool,
+}
+
+impl Diffuse_light {
+
+    pub fn new( emit: &Color ) -> Self {
+        Self { emit: Emission::Rgb( *emit ), one_sided: false }
+    }
+
+    // Only emits from the front face, like an area light's panel.
+    pub fn one_sided( self ) -> Self {
+        Self { one_sided: true, ..self }
+    }
+
+    // Incandescent emitter at the given temperature in Kelvin, see blackbody_rgb.
+    pub fn blackbody( temperature: f64, intensity: f64, normalize: bool ) -> Self {
+        Self { emit: Emission::blackbody( temperature, intensity, normalize ), one_sided: false }
+    }
+}
+
+impl Material for Diffuse_light {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+        ( false, Color( 0.0, 0.0, 0.0 ), r_in.spawn( rec.p, rec.normal ))
+    }
+
+    fn emitted( &self, r_in: &Ray, rec: &Hit_record ) -> Color {
+        if self.one_sided && !rec.front_face { Color( 0.0, 0.0, 0.0 ) } else { self.emit.at( r_in.wavelength() ) }
+    }
+}
+
 pub struct Lambertian {
     albedo: Color,
 }
@@ -37,11 +126,204 @@ impl Material for Lambertian {
             scatter_direction = rec.normal;
         }
 
-        let scattered   = Ray( rec.p, scatter_direction );
-        let attenuation = self.albedo;
+        let scattered   = r_in.spawn( rec.p, scatter_direction );
+        let attenuation = at_wavelength( &self.albedo, r_in.wavelength() );
 
         ( true, attenuation, scattered )
     }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+        let cosine = dot( rec.normal, wi );
+        if cosine > 0.0 { at_wavelength( &self.albedo, r_in.wavelength() ) * ( cosine / PI ) } else { Color( 0.0, 0.0, 0.0 ) }
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        f64::max( dot( rec.normal, wi ), 0.0 ) / PI
+    }
+}
+
+// Oren-Nayar rough diffuse, qualitative model. sigma is the standard deviation
+// of the microfacet slopes in degrees; zero gives back Lambertian.
+pub struct Oren_nayar {
+    albedo: Color,
+    a: f64,
+    b: f64,
+}
+
+impl Oren_nayar {
+
+    pub fn new( albedo: &Color, sigma: f64 ) -> Self {
+
+        let sigma  = degrees_to_radians( sigma );
+        let sigma2 = sigma * sigma;
+
+        Self { albedo: *albedo
+             , a: 1.0 - sigma2 / ( 2.0 * ( sigma2 + 0.33 ))
+             , b: 0.45 * sigma2 / ( sigma2 + 0.09 ) }
+    }
+
+    // BRDF over albedo / PI, in the local frame.
+    fn factor( &self, wo: Vec3f, wi: Vec3f ) -> f64 {
+
+        let sin_o = f64::sqrt( f64::max( 0.0, 1.0 - wo.z() * wo.z() ));
+        let sin_i = f64::sqrt( f64::max( 0.0, 1.0 - wi.z() * wi.z() ));
+
+        let max_cos = if sin_i > 1e-4 && sin_o > 1e-4 {
+            f64::max( 0.0, ( wi.x() * wo.x() + wi.y() * wo.y() ) / ( sin_i * sin_o ))
+        } else {
+            0.0
+        };
+
+        let ( sin_alpha, tan_beta ) = if wi.z() < wo.z() {
+            ( sin_i, sin_o / wo.z() )
+        } else {
+            ( sin_o, sin_i / wi.z() )
+        };
+
+        self.a + self.b * max_cos * sin_alpha * tan_beta
+    }
+}
+
+impl Material for Oren_nayar {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = Vec3f::random_cosine_direction();
+
+        let scattered   = r_in.spawn( rec.p, onb.transform( wi ));
+        let attenuation = self.factor( wo, wi ) * at_wavelength( &self.albedo, r_in.wavelength() );
+
+        ( wo.z() > 0.0, attenuation, scattered )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = onb.to_local( wi );
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        ( self.factor( wo, wi ) * wi.z() / PI ) * at_wavelength( &self.albedo, r_in.wavelength() )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        f64::max( dot( rec.normal, wi ), 0.0 ) / PI
+    }
+}
+
+// MERL isotropic BRDF table (Matusik et al., "A Data-Driven Reflectance
+// Model"), indexed by Rusinkiewicz's half and difference angles. Sampled with
+// a cosine lobe, so sharp highlights rely on light sampling to converge.
+pub struct Measured_brdf {
+    table: Vec<f64>,
+}
+
+const MERL_THETA_H: usize = 90;
+const MERL_THETA_D: usize = 90;
+const MERL_PHI_D: usize   = 180;
+const MERL_SCALE: [ f64; 3 ] = [ 1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0 ];
+
+impl Measured_brdf {
+
+    // Reads a MERL .binary file: three little endian i32 dimensions, then the
+    // red, green and blue tables as f64.
+    pub fn load( path: &str ) -> io::Result<Self> {
+
+        let bytes   = fs::read( path )?;
+        let invalid = | message: &str | io::Error::new( io::ErrorKind::InvalidData, message.to_string() );
+
+        if bytes.len() < 12 {
+            return Err( invalid( "truncated merl file" ))
+        }
+
+        let dims: Vec<usize> = bytes[0..12].chunks_exact( 4 )
+                                           .map( |b| i32::from_le_bytes( [ b[0], b[1], b[2], b[3] ] ) as usize )
+                                           .collect();
+
+        if dims != [ MERL_THETA_H, MERL_THETA_D, MERL_PHI_D ] {
+            return Err( invalid( "unexpected merl dimensions" ))
+        }
+
+        let count = 3 * MERL_THETA_H * MERL_THETA_D * MERL_PHI_D;
+        if bytes.len() < 12 + count * 8 {
+            return Err( invalid( "truncated merl file" ))
+        }
+
+        let table = bytes[12..12 + count * 8].chunks_exact( 8 ).map( |b| {
+            f64::from_le_bytes( [ b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7] ] )
+        }).collect();
+
+        Ok( Self { table } )
+    }
+
+    // BRDF for local directions, z being the normal.
+    fn lookup( &self, wo: Vec3f, wi: Vec3f ) -> Color {
+
+        let wh      = ( wo + wi ).normalize();
+        let theta_h = f64::acos( f64::clamp( wh.z(), -1.0, 1.0 ));
+        let phi_h   = f64::atan2( wh.y(), wh.x() );
+
+        // wi in the frame where the half vector is the pole.
+        let ( sin, cos ) = f64::sin_cos( -phi_h );
+        let d            = Vec3f( cos * wi.x() - sin * wi.y(), sin * wi.x() + cos * wi.y(), wi.z() );
+        let ( sin, cos ) = f64::sin_cos( -theta_h );
+        let d            = Vec3f( cos * d.x() + sin * d.z(), d.y(), cos * d.z() - sin * d.x() );
+
+        let theta_d = f64::acos( f64::clamp( d.z(), -1.0, 1.0 ));
+        let phi_d   = f64::atan2( d.y(), d.x() );
+        let phi_d   = if phi_d < 0.0 { phi_d + PI } else { phi_d };
+
+        // theta_h bins are denser near the highlight.
+        let theta_h_index = ( f64::sqrt( theta_h / ( 0.5 * PI )) * MERL_THETA_H as f64 ) as usize;
+        let theta_d_index = ( theta_d / ( 0.5 * PI ) * MERL_THETA_D as f64 ) as usize;
+        let phi_d_index   = ( phi_d / PI * MERL_PHI_D as f64 ) as usize;
+
+        let index = usize::min( phi_d_index, MERL_PHI_D - 1 )
+                  + usize::min( theta_d_index, MERL_THETA_D - 1 ) * MERL_PHI_D
+                  + usize::min( theta_h_index, MERL_THETA_H - 1 ) * MERL_PHI_D * MERL_THETA_D;
+
+        let stride  = MERL_THETA_H * MERL_THETA_D * MERL_PHI_D;
+        let channel = | c: usize | f64::max( self.table[index + c * stride] * MERL_SCALE[c], 0.0 );
+
+        Color( channel( 0 ), channel( 1 ), channel( 2 ))
+    }
+}
+
+impl Material for Measured_brdf {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = Vec3f::random_cosine_direction();
+
+        let scattered   = r_in.spawn( rec.p, onb.transform( wi ));
+        let attenuation = at_wavelength( &( PI * self.lookup( wo, wi )), r_in.wavelength() );
+
+        ( wo.z() > 0.0, attenuation, scattered )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = onb.to_local( wi );
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        at_wavelength( &( wi.z() * self.lookup( wo, wi )), r_in.wavelength() )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        f64::max( dot( rec.normal, wi ), 0.0 ) / PI
+    }
 }
 
 pub struct Metal {
@@ -62,21 +344,71 @@ impl Material for Metal {
 
         let reflected   = Vec3f::reflect( r_in.direction(), rec.normal );
         let reflected   = reflected.normalize() + self.fuzz * Vec3f::random_unit_vector();
-        let scattered   = Ray( rec.p, reflected );
-        let attenuation = self.albedo;
+        let scattered   = r_in.spawn( rec.p, reflected );
+        let attenuation = at_wavelength( &self.albedo, r_in.wavelength() );
         let bool_ret    = dot( scattered.direction(), rec.normal ) > 0.0;
 
         ( bool_ret, attenuation, scattered )
     }
 }
 
+// Thin dielectric coating, like soap or oil, whose interference tints the
+// reflection. The thickness texture is read in nm from its first channel.
+pub struct Thin_film {
+    thickness: Arc<dyn Texture>,
+    ior: f64,
+}
+
+impl Thin_film {
+
+    pub fn new( thickness: Arc<dyn Texture>, ior: f64 ) -> Self {
+        Self { thickness, ior }
+    }
+
+    pub fn constant( thickness: f64, ior: f64 ) -> Self {
+        Self::new( Arc::new( Solid_color::scalar( thickness )), ior )
+    }
+
+    // Reflectance of the film lying between a medium of IOR eta_i and a
+    // substrate of complex IOR eta_t + i k_t. Spectral rays get it at their own
+    // wavelengths, RGB ones integrated over the spectrum.
+    fn reflectance( &self, r_in: &Ray, rec: &Hit_record, cos_i: f64, eta_i: f64, eta_t: &Color, k_t: &Color ) -> Color {
+
+        let thickness = f64::max( self.thickness.value( rec.u, rec.v, &rec.p ).x(), 0.0 );
+
+        let spectrum = | lambda | fresnel_thin_film( cos_i
+                                                   , eta_i
+                                                   , self.ior
+                                                   , thickness
+                                                   , rgb_at_wavelength( eta_t, lambda )
+                                                   , rgb_at_wavelength( k_t, lambda )
+                                                   , lambda );
+
+        if r_in.wavelength() > 0.0 {
+            at_hero_wavelengths( spectrum, r_in.wavelength() )
+        } else {
+            reflectance_to_rgb( spectrum )
+        }
+    }
+}
+
 pub struct Dielectric {
-    refraction_index: f64,
+    refraction_index: Ior,
+    film: Option<Thin_film>,
 }
 
 impl Dielectric {
     pub fn new( refraction_index: f64 ) -> Self {
-        Self { refraction_index }
+        Self { refraction_index: Ior::Constant( refraction_index ), film: None }
+    }
+
+    // Wavelength dependent IOR, splitting white light when rendering spectrally.
+    pub fn dispersive( refraction_index: Ior ) -> Self {
+        Self { refraction_index, film: None }
+    }
+
+    pub fn with_film( self, film: Thin_film ) -> Self {
+        Self { film: Some( film ), ..self }
     }
 
     fn reflectance( cosine: f64, refraction_index: f64 ) -> f64 {
@@ -91,24 +423,962 @@ impl Material for Dielectric {
     
     fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> (bool, Color, Ray) {
 
-        let attenuation = Color( 1.0, 1.0, 1.0 );
+        let mut attenuation  = Color( 1.0, 1.0, 1.0 );
+        let refraction_index = self.refraction_index.at( r_in.wavelength() );
         let ri =
-            if rec.front_face { 1.0 / self.refraction_index } else { self.refraction_index };
+            if rec.front_face { 1.0 / refraction_index } else { refraction_index };
 
         let unit_direction = r_in.direction().normalize();
         let cos_theta      = f64::min( dot( -unit_direction, rec.normal ), 1.0 );
         let sin_theta      = f64::sqrt( 1.0 - cos_theta * cos_theta );
         let cannot_refract = ri * sin_theta > 1.0;
 
-        let direction =
-            if cannot_refract || Self::reflectance( cos_theta, ri ) > random_double() {
-                Vec3f::reflect( unit_direction, rec.normal )
+        let direction = match &self.film {
+            Some( film ) if !cannot_refract => {
+                // Pick the event by the mean reflectance and weight by the actual one.
+                let ( eta_i, eta_t ) = if rec.front_face { ( 1.0, refraction_index ) } else { ( refraction_index, 1.0 ) };
+                let reflectance      = film.reflectance( r_in, rec, cos_theta, eta_i, &Color( eta_t, eta_t, eta_t ), &Color( 0.0, 0.0, 0.0 ));
+                let p_reflect        = f64::clamp(( reflectance.0 + reflectance.1 + reflectance.2 ) / 3.0, 1e-3, 1.0 - 1e-3 );
+
+                if random_double() < p_reflect {
+                    attenuation = reflectance / p_reflect;
+                    Vec3f::reflect( unit_direction, rec.normal )
+                } else {
+                    attenuation = ( Color( 1.0, 1.0, 1.0 ) - reflectance ) / ( 1.0 - p_reflect );
+                    Vec3f::refract( unit_direction, rec.normal, ri )
+                }
+            }
+            _ => {
+                if cannot_refract || Self::reflectance( cos_theta, ri ) > random_double() {
+                    Vec3f::reflect( unit_direction, rec.normal )
+                } else {
+                    Vec3f::refract( unit_direction, rec.normal, ri )
+                }
+            }
+        };
+
+        // Other wavelengths would have bent elsewhere.
+        let dispersive = r_in.wavelength() > 0.0 && !matches!( self.refraction_index, Ior::Constant( _ ));
+        let scattered  = r_in.spawn( rec.p, direction );
+        let scattered  = if dispersive { scattered.terminate_secondary() } else { scattered };
+
+        ( true, attenuation, scattered )
+    }
+}
+
+// GGX microfacet metal with complex IOR Fresnel, sampled through visible normals.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    distribution: Ggx,
+    film: Option<Thin_film>,
+}
+
+impl Conductor {
+
+    // Different roughnesses along the tangent and the bitangent make it
+    // anisotropic, the tangent being the surface's dpdu.
+    pub fn new( eta: &Color, k: &Color, roughness_u: f64, roughness_v: f64 ) -> Self {
+        Self { eta: *eta
+             , k: *k
+             , distribution: Ggx::new( Ggx::roughness_to_alpha( roughness_u )
+                                     , Ggx::roughness_to_alpha( roughness_v ))
+             , film: None }
+    }
+
+    pub fn with_film( self, film: Thin_film ) -> Self {
+        Self { film: Some( film ), ..self }
+    }
+
+    // Shading frame following dpdu, so anisotropic highlights keep their
+    // direction across the surface.
+    fn frame( rec: &Hit_record ) -> Onb {
+        Onb::from_tangent( rec.normal, rec.dpdu )
+    }
+
+    fn fresnel( &self, r_in: &Ray, rec: &Hit_record, cos_i: f64 ) -> Color {
+
+        let lambda = r_in.wavelength();
+
+        match &self.film {
+            Some( film )         => film.reflectance( r_in, rec, cos_i, 1.0, &self.eta, &self.k ),
+            None if lambda > 0.0 => at_hero_wavelengths( | l | fresnel_conductor( cos_i
+                                                                                 , rgb_at_wavelength( &self.eta, l )
+                                                                                 , rgb_at_wavelength( &self.k, l )), lambda ),
+            None                 => fresnel_conductor_rgb( cos_i, &self.eta, &self.k ),
+        }
+    }
+
+    // RGB fits of measured spectral data.
+    pub fn gold( roughness: f64 ) -> Self {
+        Self::new( &Color( 0.143119, 0.374957, 1.44248 ), &Color( 3.98316, 2.38572, 1.60322 ), roughness, roughness )
+    }
+
+    pub fn copper( roughness: f64 ) -> Self {
+        Self::new( &Color( 0.200438, 0.924033, 1.10221 ), &Color( 3.91295, 2.45285, 2.14219 ), roughness, roughness )
+    }
+
+    pub fn aluminum( roughness: f64 ) -> Self {
+        Self::new( &Color( 1.65746, 0.880369, 0.521229 ), &Color( 9.22387, 6.26952, 4.837 ), roughness, roughness )
+    }
+
+    pub fn silver( roughness: f64 ) -> Self {
+        Self::new( &Color( 0.155265, 0.116723, 0.138342 ), &Color( 4.82835, 3.12225, 2.14696 ), roughness, roughness )
+    }
+}
+
+impl Material for Conductor {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+
+        let onb = Self::frame( rec );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+
+        if self.distribution.effectively_smooth() {
+            let wi = Vec3f( -wo.x(), -wo.y(), wo.z() );
+            let f  = self.fresnel( r_in, rec, wo.z() );
+            return ( true, f, r_in.spawn( rec.p, onb.transform( wi )))
+        }
+
+        let ( u1, u2 ) = random_2d();
+
+        let wm = self.distribution.sample_wm( wo, u1, u2 );
+        let wi = Vec3f::reflect( -wo, wm );
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return ( false, Color( 0.0, 0.0, 0.0 ), r_in.spawn( rec.p, onb.transform( wi )))
+        }
+
+        let f           = self.fresnel( r_in, rec, dot( wo, wm ));
+        let attenuation = ( self.distribution.g( wo, wi ) / self.distribution.g1( wo )) * f;
+
+        ( true, attenuation, r_in.spawn( rec.p, onb.transform( wi )))
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+
+        if self.distribution.effectively_smooth() {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        let onb = Self::frame( rec );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = onb.to_local( wi );
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        let wm = ( wo + wi ).normalize();
+        let f  = self.fresnel( r_in, rec, dot( wo, wm ));
+
+        ( self.distribution.d( wm ) * self.distribution.g( wo, wi ) / ( 4.0 * wo.z() )) * f
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+
+        if self.distribution.effectively_smooth() {
+            return 0.0
+        }
+
+        let onb = Self::frame( rec );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = onb.to_local( wi );
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0
+        }
+
+        let wm = ( wo + wi ).normalize();
+
+        self.distribution.d_visible( wo, wm ) / ( 4.0 * dot( wo, wm ))
+    }
+}
+
+// eta_t / eta_i for the interface r_in is crossing into or out of a medium of
+// the given IOR.
+fn relative_ior( r_in: &Ray, rec: &Hit_record, ior: f64 ) -> f64 {
+    if rec.front_face {
+        ior / r_in.media().ior()
+    } else {
+        r_in.media().outer_ior() / ior
+    }
+}
+
+// Ray going through the surface, entering or leaving medium.
+fn refracted_ray( r_in: &Ray, rec: &Hit_record, dir: Vec3f, medium: Medium ) -> Ray {
+
+    let mut scattered = r_in.spawn( rec.p, dir );
+
+    if rec.front_face {
+        scattered.media_mut().push( medium );
+    } else {
+        scattered.media_mut().pop();
+    }
+
+    scattered
+}
+
+// GGX microfacet glass. Light inside is tinted by Beer-Lambert absorption, tint
+// being the color left after travelling tint_distance. Rays keep a stack of the
+// media they're in, so glass inside glass refracts with the right IOR ratio.
+pub struct Rough_dielectric {
+    medium: Medium,
+    distribution: Ggx,
+}
+
+impl Rough_dielectric {
+
+    pub fn new( refraction_index: f64, roughness: f64, tint: &Color, tint_distance: f64 ) -> Self {
+
+        let sigma = | t: f64 | -f64::ln( f64::max( t, 1e-6 )) / tint_distance;
+        let alpha = Ggx::roughness_to_alpha( roughness );
+
+        Self { medium: Medium { ior: refraction_index, sigma_a: Color( sigma( tint.0 ), sigma( tint.1 ), sigma( tint.2 )) }
+             , distribution: Ggx::new( alpha, alpha ) }
+    }
+
+}
+
+impl Material for Rough_dielectric {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let eta = relative_ior( r_in, rec, self.medium.ior );
+
+        let wm = if self.distribution.effectively_smooth() {
+            Vec3f( 0.0, 0.0, 1.0 )
+        } else {
+            let ( u1, u2 ) = random_2d();
+            self.distribution.sample_wm( wo, u1, u2 )
+        };
+
+        let f                   = fresnel_dielectric( dot( wo, wm ), eta );
+        let ( can_refract, wt ) = refract_through( wo, wm, eta );
+        let reflect             = !can_refract || random_double() < f;
+
+        let ( wi, scattered ) = if reflect {
+            let wi = Vec3f::reflect( -wo, wm );
+            ( wi, r_in.spawn( rec.p, onb.transform( wi )))
+        } else {
+            ( wt, refracted_ray( r_in, rec, onb.transform( wt ), self.medium ))
+        };
+
+        if self.distribution.effectively_smooth() {
+            return ( true, Color( 1.0, 1.0, 1.0 ), scattered )
+        }
+
+        if wo.z() <= 0.0 || ( reflect && wi.z() <= 0.0 ) || ( !reflect && wi.z() >= 0.0 ) {
+            return ( false, Color( 0.0, 0.0, 0.0 ), scattered )
+        }
+
+        let weight = self.distribution.g( wo, wi ) / self.distribution.g1( wo );
+
+        ( true, Color( weight, weight, weight ), scattered )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+
+        if self.distribution.effectively_smooth() {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        let onb   = Onb::new( rec.normal );
+        let wo    = onb.to_local( -r_in.direction().normalize() );
+        let value = dielectric_eval( &self.distribution, wo, onb.to_local( wi ), relative_ior( r_in, rec, self.medium.ior ));
+
+        Color( value, value, value )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+
+        if self.distribution.effectively_smooth() {
+            return 0.0
+        }
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+
+        dielectric_pdf( &self.distribution, wo, onb.to_local( wi ), relative_ior( r_in, rec, self.medium.ior ))
+    }
+}
+
+// Disney style principled BSDF (Burley 2012, 2015). Every parameter is a
+// texture; single numbers are read from the first channel, so constants are
+// Solid_color::scalar( x ). Lobes are diffuse with sheen, GGX specular, rough
+// glass for transmission and a clearcoat, picked by their estimated weight.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: Arc<dyn Texture>,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            base_color: Arc::new( Solid_color::new( &Color( 0.8, 0.8, 0.8 ))),
+            metallic: Arc::new( Solid_color::scalar( 0.0 )),
+            roughness: Arc::new( Solid_color::scalar( 0.5 )),
+            specular: Arc::new( Solid_color::scalar( 0.5 )),
+            specular_tint: Arc::new( Solid_color::scalar( 0.0 )),
+            sheen: Arc::new( Solid_color::scalar( 0.0 )),
+            clearcoat: Arc::new( Solid_color::scalar( 0.0 )),
+            transmission: Arc::new( Solid_color::scalar( 0.0 )),
+            ior: Arc::new( Solid_color::scalar( 1.5 )),
+        }
+    }
+}
+
+// Principled parameters looked up at one hit point.
+struct Principled_point {
+    base_color: Color,
+    tint: Color,
+    f0: Color,
+    roughness: f64,
+    sheen: f64,
+    clearcoat: f64,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    glass_weight: f64,
+    ior: f64,
+    eta: f64,
+    distribution: Ggx,
+    clearcoat_distribution: Ggx,
+    // Lobe probabilities, diffuse, specular, glass and clearcoat.
+    probabilities: [ f64; 4 ],
+}
+
+const CLEARCOAT_ALPHA: f64 = 0.05;
+
+fn schlick( f0: f64, cosine: f64 ) -> f64 {
+    f0 + ( 1.0 - f0 ) * f64::powi( 1.0 - f64::clamp( cosine, 0.0, 1.0 ), 5 )
+}
+
+fn schlick_rgb( f0: &Color, cosine: f64 ) -> Color {
+    Color( schlick( f0.0, cosine ), schlick( f0.1, cosine ), schlick( f0.2, cosine ))
+}
+
+fn lerp( a: &Color, b: &Color, t: f64 ) -> Color {
+    ( 1.0 - t ) * *a + t * *b
+}
+
+impl Principled {
+
+    fn lookup( &self, r_in: &Ray, rec: &Hit_record, wo: Vec3f ) -> Principled_point {
+
+        let color  = | texture: &Arc<dyn Texture> | texture.value( rec.u, rec.v, &rec.p );
+        let scalar = | texture: &Arc<dyn Texture> | color( texture ).x();
+
+        let base_color    = color( &self.base_color );
+        let metallic      = f64::clamp( scalar( &self.metallic ), 0.0, 1.0 );
+        let roughness     = f64::clamp( scalar( &self.roughness ), 0.0, 1.0 );
+        let specular      = scalar( &self.specular );
+        let specular_tint = scalar( &self.specular_tint );
+        let transmission  = f64::clamp( scalar( &self.transmission ), 0.0, 1.0 );
+        let ior           = scalar( &self.ior );
+        let eta           = relative_ior( r_in, rec, ior );
+
+        let white = Color( 1.0, 1.0, 1.0 );
+        let lum   = luminance( &base_color );
+        let tint  = if lum > 0.0 { base_color / lum } else { white };
+
+        // specular = 0.5 gives the reflectance the IOR predicts.
+        let dielectric_f0 = 2.0 * specular * (( eta - 1.0 ) / ( eta + 1.0 )) * (( eta - 1.0 ) / ( eta + 1.0 ));
+        let f0            = lerp( &( dielectric_f0 * lerp( &white, &tint, specular_tint )), &base_color, metallic );
+
+        let diffuse_weight  = ( 1.0 - metallic ) * ( 1.0 - transmission );
+        let glass_weight    = ( 1.0 - metallic ) * transmission;
+        let specular_weight = 1.0 - glass_weight;
+        let clearcoat       = 0.25 * scalar( &self.clearcoat );
+
+        let mut probabilities = [ diffuse_weight * lum
+                                , specular_weight * luminance( &schlick_rgb( &f0, wo.z() ))
+                                , glass_weight
+                                , clearcoat * schlick( 0.04, wo.z() ) ];
+
+        let total: f64 = probabilities.iter().sum();
+        if total > 0.0 {
+            for p in probabilities.iter_mut() { *p /= total; }
+        } else {
+            probabilities = [ 1.0, 0.0, 0.0, 0.0 ];
+        }
+
+        let alpha = f64::max( Ggx::roughness_to_alpha( roughness ), 1e-3 );
+
+        Principled_point { base_color
+                         , tint
+                         , f0
+                         , roughness
+                         , sheen: scalar( &self.sheen )
+                         , clearcoat
+                         , diffuse_weight
+                         , specular_weight
+                         , glass_weight
+                         , ior
+                         , eta
+                         , distribution: Ggx::new( alpha, alpha )
+                         , clearcoat_distribution: Ggx::new( CLEARCOAT_ALPHA, CLEARCOAT_ALPHA )
+                         , probabilities }
+    }
+
+    fn eval_local( point: &Principled_point, wo: Vec3f, wi: Vec3f ) -> Color {
+
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        let glass = dielectric_eval( &point.distribution, wo, wi, point.eta ) * point.glass_weight;
+
+        if wi.z() < 0.0 {
+            return glass * point.base_color
+        }
+
+        let wm     = ( wo + wi ).normalize();
+        let cos_d  = dot( wi, wm );
+        let fl     = f64::powi( 1.0 - wi.z(), 5 );
+        let fv     = f64::powi( 1.0 - wo.z(), 5 );
+        let fd90   = 0.5 + 2.0 * point.roughness * cos_d * cos_d;
+        let burley = ( 1.0 + ( fd90 - 1.0 ) * fl ) * ( 1.0 + ( fd90 - 1.0 ) * fv ) / PI;
+
+        let sheen_color = lerp( &Color( 1.0, 1.0, 1.0 ), &point.tint, 0.5 );
+        let diffuse     = ( burley * point.base_color + ( point.sheen * f64::powi( 1.0 - cos_d, 5 )) * sheen_color ) * wi.z();
+
+        let specular = ( point.distribution.d( wm ) * point.distribution.g( wo, wi ) / ( 4.0 * wo.z() ))
+                     * schlick_rgb( &point.f0, dot( wo, wm ));
+
+        let clearcoat = point.clearcoat * schlick( 0.04, dot( wo, wm ))
+                      * point.clearcoat_distribution.d( wm ) * point.clearcoat_distribution.g( wo, wi ) / ( 4.0 * wo.z() );
+
+        point.diffuse_weight * diffuse + point.specular_weight * specular
+      + Color( glass + clearcoat, glass + clearcoat, glass + clearcoat )
+    }
+
+    fn pdf_local( point: &Principled_point, wo: Vec3f, wi: Vec3f ) -> f64 {
+
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return 0.0
+        }
+
+        let glass = point.probabilities[2] * dielectric_pdf( &point.distribution, wo, wi, point.eta );
+
+        if wi.z() < 0.0 {
+            return glass
+        }
+
+        let wm = ( wo + wi ).normalize();
+
+        point.probabilities[0] * wi.z() / PI
+      + point.probabilities[1] * point.distribution.d_visible( wo, wm ) / ( 4.0 * dot( wo, wm ))
+      + glass
+      + point.probabilities[3] * point.clearcoat_distribution.d_visible( wo, wm ) / ( 4.0 * dot( wo, wm ))
+    }
+}
+
+impl Material for Principled {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+
+        let onb   = Onb::new( rec.normal );
+        let wo    = onb.to_local( -r_in.direction().normalize() );
+        let point = self.lookup( r_in, rec, wo );
+
+        let reflect_on = | distribution: &Ggx | {
+            let ( u1, u2 ) = random_2d();
+            Vec3f::reflect( -wo, distribution.sample_wm( wo, u1, u2 ))
+        };
+
+        let u  = random_double();
+        let p  = point.probabilities;
+        let wi =
+            if u < p[0] {
+                Vec3f::random_cosine_direction()
+            } else if u < p[0] + p[1] {
+                reflect_on( &point.distribution )
+            } else if u < p[0] + p[1] + p[2] {
+                let ( u1, u2 )          = random_2d();
+                let wm                  = point.distribution.sample_wm( wo, u1, u2 );
+                let ( can_refract, wt ) = refract_through( wo, wm, point.eta );
+                if !can_refract || random_double() < fresnel_dielectric( dot( wo, wm ), point.eta ) {
+                    Vec3f::reflect( -wo, wm )
+                } else {
+                    wt
+                }
+            } else {
+                reflect_on( &point.clearcoat_distribution )
+            };
+
+        let scattered =
+            if wi.z() < 0.0 {
+                refracted_ray( r_in, rec, onb.transform( wi ), Medium { ior: point.ior, sigma_a: Color( 0.0, 0.0, 0.0 ) } )
             } else {
-                Vec3f::refract( unit_direction, rec.normal, ri )
+                r_in.spawn( rec.p, onb.transform( wi ))
             };
 
-        let scattered = Ray( rec.p, direction );
+        let pdf = Self::pdf_local( &point, wo, wi );
+        if pdf <= 0.0 {
+            return ( false, Color( 0.0, 0.0, 0.0 ), scattered )
+        }
 
-        ( true, attenuation, scattered )
+        ( true, at_wavelength( &( Self::eval_local( &point, wo, wi ) / pdf ), r_in.wavelength() ), scattered )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        at_wavelength( &Self::eval_local( &self.lookup( r_in, rec, wo ), wo, onb.to_local( wi )), r_in.wavelength() )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        Self::pdf_local( &self.lookup( r_in, rec, wo ), wo, onb.to_local( wi ))
+    }
+}
+
+// Smooth dielectric coating of some thickness over any material, like varnish or
+// car paint clearcoat. Light entering the coat is absorbed by sigma_a on its way
+// through, and light reflected back inside bounces off the base again, up to
+// MAX_COAT_BOUNCES times. Only light leaving after one bounce is evaluated for
+// light sampling, longer walks are left to scatter().
+pub struct Layered {
+    base: Arc<dyn Material + Send + Sync>,
+    ior: f64,
+    thickness: f64,
+    sigma_a: Color,
+}
+
+const MAX_COAT_BOUNCES: i32 = 8;
+
+impl Layered {
+
+    pub fn new( base: Arc<dyn Material + Send + Sync>, ior: f64, thickness: f64, sigma_a: &Color ) -> Self {
+        Self { base, ior, thickness, sigma_a: *sigma_a }
+    }
+
+    // Direction inside the coat of a local direction outside it, both pointing up.
+    fn inside( w: Vec3f, eta: f64 ) -> Vec3f {
+        let ( x, y ) = ( w.x() / eta, w.y() / eta );
+        Vec3f( x, y, f64::sqrt( f64::max( 0.0, 1.0 - x * x - y * y )))
+    }
+
+    // The opposite, false when it can't get out of the coat.
+    fn outside( w: Vec3f, eta: f64 ) -> ( bool, Vec3f ) {
+        let ( x, y ) = ( w.x() * eta, w.y() * eta );
+        let sin2     = x * x + y * y;
+        ( sin2 < 1.0, Vec3f( x, y, f64::sqrt( f64::max( 0.0, 1.0 - sin2 ))))
+    }
+
+    // Crossing the coat once along w.
+    fn absorption( &self, w: Vec3f ) -> Color {
+        let distance = self.thickness / f64::abs( w.z() );
+        Color( f64::exp( -self.sigma_a.0 * distance )
+             , f64::exp( -self.sigma_a.1 * distance )
+             , f64::exp( -self.sigma_a.2 * distance ))
+    }
+
+    // Jacobian and Fresnel factors of a single bounce leaving along wi.
+    fn single_bounce( &self, wo: Vec3f, wi: Vec3f, eta: f64 ) -> ( f64, Vec3f, Vec3f ) {
+
+        let wo_in = Self::inside( wo, eta );
+        let wi_in = Self::inside( wi, eta );
+
+        let transmission = ( 1.0 - fresnel_dielectric( wo.z(), eta )) * ( 1.0 - fresnel_dielectric( wi.z(), eta ));
+
+        ( transmission * wi.z() / ( eta * eta * wi_in.z() ), wo_in, wi_in )
+    }
+}
+
+impl Material for Layered {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+        let ( bool_scat, attenuation, scattered, _ ) = self.scatter_with_pdf( r_in, rec );
+        ( bool_scat, attenuation, scattered )
+    }
+
+    fn scatter_with_pdf( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray, f64 ) {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let eta = self.ior / r_in.media().ior();
+        let f_o = fresnel_dielectric( wo.z(), eta );
+
+        if wo.z() <= 0.0 || random_double() < f_o {
+            let mirror = Vec3f( -wo.x(), -wo.y(), wo.z() );
+            return ( wo.z() > 0.0, Color( 1.0, 1.0, 1.0 ), r_in.spawn( rec.p, onb.transform( mirror )), 0.0 )
+        }
+
+        let mut down       = -Self::inside( wo, eta );
+        let mut throughput = Color( 1.0, 1.0, 1.0 );
+
+        for _ in 0..MAX_COAT_BOUNCES {
+
+            let below = r_in.spawn( rec.p, onb.transform( down ));
+
+            let ( bool_scat, attenuation, scattered ) = self.base.scatter( &below, rec );
+            let wi_in = onb.to_local( scattered.direction().normalize() );
+
+            // Transmitted by the base, nothing for the coat to do.
+            if !bool_scat || wi_in.z() <= 0.0 {
+                return ( bool_scat, throughput * attenuation, scattered, 0.0 )
+            }
+
+            throughput = throughput * attenuation * at_wavelength( &( self.absorption( down ) * self.absorption( wi_in )), r_in.wavelength() );
+
+            let ( leaves, wi ) = Self::outside( wi_in, eta );
+            let f_i            = if leaves { fresnel_dielectric( wi.z(), eta ) } else { 1.0 };
+
+            if random_double() >= f_i {
+                let wi = onb.transform( wi );
+                return ( true, throughput, r_in.spawn( rec.p, wi ), self.scattering_pdf( r_in, rec, wi ))
+            }
+
+            down = Vec3f( wi_in.x(), wi_in.y(), -wi_in.z() );
+        }
+
+        ( false, Color( 0.0, 0.0, 0.0 ), r_in.spawn( rec.p, rec.normal ), 0.0 )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = onb.to_local( wi );
+        let eta = self.ior / r_in.media().ior();
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        let ( factor, wo_in, wi_in ) = self.single_bounce( wo, wi, eta );
+
+        let below  = r_in.spawn( rec.p, onb.transform( -wo_in ));
+        let base_f = self.base.eval( &below, rec, onb.transform( wi_in ));
+
+        factor * base_f * at_wavelength( &( self.absorption( wo_in ) * self.absorption( wi_in )), r_in.wavelength() )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+
+        let onb = Onb::new( rec.normal );
+        let wo  = onb.to_local( -r_in.direction().normalize() );
+        let wi  = onb.to_local( wi );
+        let eta = self.ior / r_in.media().ior();
+
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0
+        }
+
+        let wo_in = Self::inside( wo, eta );
+        let wi_in = Self::inside( wi, eta );
+        let below = r_in.spawn( rec.p, onb.transform( -wo_in ));
+
+        // Base lobe seen through the coat, leaving along wi.
+        let pdf_base = ( 1.0 - fresnel_dielectric( wi.z(), eta )) * wi.z() / ( eta * eta * wi_in.z() )
+                     * self.base.scattering_pdf( &below, rec, onb.transform( wi_in ));
+
+        // The smooth coat is a delta lobe, it adds nothing off the mirror direction.
+        let p_coat   = fresnel_dielectric( wo.z(), eta );
+        let pdf_coat = 0.0;
+
+        p_coat * pdf_coat + ( 1.0 - p_coat ) * pdf_base
+    }
+
+    // The coat follows the base's holes.
+    fn is_cutout( &self, rec: &Hit_record ) -> bool {
+        self.base.is_cutout( rec )
+    }
+}
+
+// Picks second with probability weight, first otherwise.
+pub struct Mix {
+    first: Arc<dyn Material + Send + Sync>,
+    second: Arc<dyn Material + Send + Sync>,
+    weight: Arc<dyn Texture>,
+}
+
+impl Mix {
+
+    pub fn new( first: Arc<dyn Material + Send + Sync>, second: Arc<dyn Material + Send + Sync>
+              , weight: Arc<dyn Texture> ) -> Self {
+        Self { first, second, weight }
+    }
+
+    fn weight( &self, rec: &Hit_record ) -> f64 {
+        f64::clamp( self.weight.value( rec.u, rec.v, &rec.p ).x(), 0.0, 1.0 )
+    }
+}
+
+impl Material for Mix {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+        let ( bool_scat, attenuation, scattered, _ ) = self.scatter_with_pdf( r_in, rec );
+        ( bool_scat, attenuation, scattered )
+    }
+
+    fn scatter_with_pdf( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray, f64 ) {
+
+        let t      = self.weight( rec );
+        let chosen = if random_double() < t { &self.second } else { &self.first };
+
+        let ( bool_scat, attenuation, scattered, pdf ) = chosen.scatter_with_pdf( r_in, rec );
+
+        // Specular picks stay specular, glossy ones are weighted by the whole mixture.
+        let pdf = if pdf > 0.0 { self.scattering_pdf( r_in, rec, scattered.direction().normalize() ) } else { 0.0 };
+
+        ( bool_scat, attenuation, scattered, pdf )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+        let t = self.weight( rec );
+        ( 1.0 - t ) * self.first.eval( r_in, rec, wi ) + t * self.second.eval( r_in, rec, wi )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        let t = self.weight( rec );
+        ( 1.0 - t ) * self.first.scattering_pdf( r_in, rec, wi ) + t * self.second.scattering_pdf( r_in, rec, wi )
+    }
+
+    // The holes of the side the weight picks, like scatter() does.
+    fn is_cutout( &self, rec: &Hit_record ) -> bool {
+        if random_double() < self.weight( rec ) { self.second.is_cutout( rec ) } else { self.first.is_cutout( rec ) }
+    }
+}
+
+enum Normal_source {
+    // Tangent space normals, with +x along dpdu and +y along dpdv.
+    Tangent_space { texture: Arc<dyn Texture>, strength: f64 },
+    // Heights along the normal, read from the first channel.
+    Height { texture: Arc<dyn Texture>, scale: f64 },
+}
+
+// Bends the shading normal of base with a normal map or a bump map, leaving the
+// geometry flat.
+pub struct Normal_mapped {
+    base: Arc<dyn Material + Send + Sync>,
+    source: Normal_source,
+}
+
+// Texture coordinate step for the bump map finite differences.
+const BUMP_DELTA: f64 = 0.0005;
+
+impl Normal_mapped {
+
+    // strength scales the map's tilt, 1.0 using it as is.
+    pub fn new( base: Arc<dyn Material + Send + Sync>, normals: Arc<dyn Texture>, strength: f64 ) -> Self {
+        Self { base, source: Normal_source::Tangent_space { texture: normals, strength } }
+    }
+
+    // Heights are multiplied by scale, in world units.
+    pub fn bump( base: Arc<dyn Material + Send + Sync>, heights: Arc<dyn Texture>, scale: f64 ) -> Self {
+        Self { base, source: Normal_source::Height { texture: heights, scale } }
+    }
+
+    fn shading_record( &self, rec: &Hit_record ) -> Hit_record {
+
+        let n    = rec.outward_normal();
+        let dpdu = rec.dpdu - dot( rec.dpdu, n ) * n;
+
+        // Without tangents there is no frame to perturb in.
+        if dpdu.norm_sq() == 0.0 || rec.dpdv.norm_sq() == 0.0 {
+            return rec.clone()
+        }
+
+        let bent = match &self.source {
+
+            Normal_source::Tangent_space { texture, strength } => {
+                let c         = texture.value( rec.u, rec.v, &rec.p );
+                let tangent   = dpdu.normalize();
+                let bitangent = cross( n, tangent );
+                let bitangent = if dot( bitangent, rec.dpdv ) < 0.0 { -bitangent } else { bitangent };
+
+                let ( x, y, z ) = ( 2.0 * c.x() - 1.0, 2.0 * c.y() - 1.0, 2.0 * c.z() - 1.0 );
+
+                ( *strength * x ) * tangent + ( *strength * y ) * bitangent + f64::max( z, 1e-3 ) * n
+            }
+
+            Normal_source::Height { texture, scale } => {
+                let height = | u: f64, v: f64, p: &Point3 | *scale * texture.value( u, v, p ).x();
+
+                let h  = height( rec.u, rec.v, &rec.p );
+                let hu = height( rec.u + BUMP_DELTA, rec.v, &( rec.p + BUMP_DELTA * rec.dpdu ));
+                let hv = height( rec.u, rec.v + BUMP_DELTA, &( rec.p + BUMP_DELTA * rec.dpdv ));
+
+                let dpdu = rec.dpdu + (( hu - h ) / BUMP_DELTA ) * n;
+                let dpdv = rec.dpdv + (( hv - h ) / BUMP_DELTA ) * n;
+
+                let bent = cross( dpdu, dpdv );
+                if dot( bent, n ) < 0.0 { -bent } else { bent }
+            }
+        };
+
+        let bent = bent.normalize();
+
+        let mut shading = rec.clone();
+        shading.normal  = if rec.front_face { bent } else { -bent };
+        shading
+    }
+}
+
+// Directions the bent normal allows but the real surface doesn't are dropped.
+impl Material for Normal_mapped {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+
+        let shading = self.shading_record( rec );
+        let ( bool_scat, attenuation, scattered ) = self.base.scatter( r_in, &shading );
+
+        ( bool_scat && shading.same_side( scattered.direction() ), attenuation, scattered )
+    }
+
+    fn scatter_with_pdf( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray, f64 ) {
+
+        let shading = self.shading_record( rec );
+        let ( bool_scat, attenuation, scattered, pdf ) = self.base.scatter_with_pdf( r_in, &shading );
+
+        ( bool_scat && shading.same_side( scattered.direction() ), attenuation, scattered, pdf )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+
+        let shading = self.shading_record( rec );
+
+        if !shading.same_side( wi ) {
+            return Color( 0.0, 0.0, 0.0 )
+        }
+
+        self.base.eval( r_in, &shading, wi )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+
+        let shading = self.shading_record( rec );
+
+        if !shading.same_side( wi ) {
+            return 0.0
+        }
+
+        self.base.scattering_pdf( r_in, &shading, wi )
+    }
+
+    fn emitted( &self, r_in: &Ray, rec: &Hit_record ) -> Color {
+        self.base.emitted( r_in, rec )
+    }
+
+    fn is_cutout( &self, rec: &Hit_record ) -> bool {
+        self.base.is_cutout( rec )
+    }
+}
+
+// Threshold cuts hard edges, stochastic lets a ray through with probability
+// 1 - alpha, giving soft, semi transparent ones.
+enum Alpha_mode {
+    Threshold( f64 ),
+    Stochastic,
+}
+
+// Cuts holes in base where the alpha texture, read from its first channel, is
+// low. Think leaves or fences on a single quad.
+pub struct Alpha_mask {
+    base: Arc<dyn Material + Send + Sync>,
+    alpha: Arc<dyn Texture>,
+    mode: Alpha_mode,
+}
+
+impl Alpha_mask {
+
+    pub fn new( base: Arc<dyn Material + Send + Sync>, alpha: Arc<dyn Texture>, threshold: f64 ) -> Self {
+        Self { base, alpha, mode: Alpha_mode::Threshold( threshold ) }
+    }
+
+    pub fn stochastic( base: Arc<dyn Material + Send + Sync>, alpha: Arc<dyn Texture> ) -> Self {
+        Self { base, alpha, mode: Alpha_mode::Stochastic }
+    }
+}
+
+impl Material for Alpha_mask {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+        self.base.scatter( r_in, rec )
+    }
+
+    fn scatter_with_pdf( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray, f64 ) {
+        self.base.scatter_with_pdf( r_in, rec )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+        self.base.eval( r_in, rec, wi )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        self.base.scattering_pdf( r_in, rec, wi )
+    }
+
+    fn emitted( &self, r_in: &Ray, rec: &Hit_record ) -> Color {
+        self.base.emitted( r_in, rec )
+    }
+
+    fn is_cutout( &self, rec: &Hit_record ) -> bool {
+
+        let alpha = self.alpha.value( rec.u, rec.v, &rec.p ).x();
+
+        let cut = match self.mode {
+            Alpha_mode::Threshold( threshold ) => alpha < threshold,
+            Alpha_mode::Stochastic             => random_double() >= alpha,
+        };
+
+        cut || self.base.is_cutout( rec )
+    }
+}
+
+// Different materials on the front and the back faces, like a leaf or a
+// printed sheet of paper.
+pub struct Two_sided {
+    front: Arc<dyn Material + Send + Sync>,
+    back: Arc<dyn Material + Send + Sync>,
+}
+
+impl Two_sided {
+
+    pub fn new( front: Arc<dyn Material + Send + Sync>, back: Arc<dyn Material + Send + Sync> ) -> Self {
+        Self { front, back }
+    }
+
+    fn side( &self, rec: &Hit_record ) -> &Arc<dyn Material + Send + Sync> {
+        if rec.front_face { &self.front } else { &self.back }
+    }
+}
+
+impl Material for Two_sided {
+
+    fn scatter( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray ) {
+        self.side( rec ).scatter( r_in, rec )
+    }
+
+    fn scatter_with_pdf( &self, r_in: &Ray, rec: &Hit_record ) -> ( bool, Color, Ray, f64 ) {
+        self.side( rec ).scatter_with_pdf( r_in, rec )
+    }
+
+    fn eval( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> Color {
+        self.side( rec ).eval( r_in, rec, wi )
+    }
+
+    fn scattering_pdf( &self, r_in: &Ray, rec: &Hit_record, wi: Vec3f ) -> f64 {
+        self.side( rec ).scattering_pdf( r_in, rec, wi )
+    }
+
+    fn emitted( &self, r_in: &Ray, rec: &Hit_record ) -> Color {
+        self.side( rec ).emitted( r_in, rec )
+    }
+
+    fn is_cutout( &self, rec: &Hit_record ) -> bool {
+        self.side( rec ).is_cutout( rec )
     }
 }
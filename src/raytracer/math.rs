@@ -3,6 +3,9 @@ pub mod vec3f;
 pub mod random;
 pub mod interval;
 pub mod utilities;
+pub mod onb;
+pub mod distribution;
+pub mod complex;
 
 pub use std::f64::INFINITY;
 pub use std::f64::consts::PI;
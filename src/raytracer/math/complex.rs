@@ -0,0 +1,72 @@
+use std::ops::{ Add, Sub, Mul, Div };
+
+#[derive( Clone, Copy )]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+
+    pub fn new( re: f64, im: f64 ) -> Self { Self { re, im } }
+
+    pub fn real( re: f64 ) -> Self { Self { re, im: 0.0 } }
+
+    pub fn norm_sqr( &self ) -> f64 { self.re * self.re + self.im * self.im }
+
+    // Principal square root, the one with a non negative real part.
+    pub fn sqrt( &self ) -> Complex {
+
+        let n = f64::sqrt( self.norm_sqr() );
+        if n == 0.0 {
+            return Complex::real( 0.0 )
+        }
+
+        let re = f64::sqrt( 0.5 * ( n + self.re ));
+        let im = f64::sqrt( 0.5 * ( n - self.re ));
+
+        Complex::new( re, if self.im < 0.0 { -im } else { im } )
+    }
+
+    // e^( i z )
+    pub fn exp_i( &self ) -> Complex {
+        let scale = f64::exp( -self.im );
+        Complex::new( scale * f64::cos( self.re ), scale * f64::sin( self.re ))
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add( self, rhs: Complex ) -> Complex {
+        Complex::new( self.re + rhs.re, self.im + rhs.im )
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub( self, rhs: Complex ) -> Complex {
+        Complex::new( self.re - rhs.re, self.im - rhs.im )
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul( self, rhs: Complex ) -> Complex {
+        Complex::new( self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re )
+    }
+}
+
+impl Mul<Complex> for f64 {
+    type Output = Complex;
+    fn mul( self, rhs: Complex ) -> Complex {
+        Complex::new( self * rhs.re, self * rhs.im )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div( self, rhs: Complex ) -> Complex {
+        let d = rhs.norm_sqr();
+        Complex::new(( self.re * rhs.re + self.im * rhs.im ) / d, ( self.im * rhs.re - self.re * rhs.im ) / d )
+    }
+}
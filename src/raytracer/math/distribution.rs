@@ -0,0 +1,151 @@
+// Piecewise-constant distributions, as in pbrt's Distribution1D/Distribution2D.
+pub struct Distribution_1d {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution_1d {
+
+    pub fn new( func: &[f64] ) -> Self {
+
+        let n       = func.len();
+        let mut cdf = vec![ 0.0; n + 1 ];
+
+        for i in 1..=n {
+            cdf[i] = cdf[i - 1] + f64::abs( func[i - 1] ) / n as f64;
+        }
+
+        let func_int = cdf[n];
+
+        if func_int == 0.0 {
+            for i in 1..=n { cdf[i] = i as f64 / n as f64; }
+        } else {
+            for i in 1..=n { cdf[i] /= func_int; }
+        }
+
+        Self { func: func.iter().map( |f| f64::abs( *f )).collect(), cdf, func_int }
+    }
+
+    pub fn count( &self ) -> usize { self.func.len() }
+
+    pub fn func_int( &self ) -> f64 { self.func_int }
+
+    // Returns the sampled value in [0, 1), its pdf and the bucket it fell in.
+    pub fn sample_continuous( &self, u: f64 ) -> ( f64, f64, usize ) {
+
+        let n      = self.count();
+        let offset = usize::min( self.cdf.partition_point( |c| *c <= u ).saturating_sub( 1 ), n - 1 );
+
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du    = if width > 0.0 { ( u - self.cdf[offset] ) / width } else { 0.0 };
+
+        ( ( offset as f64 + du ) / n as f64, self.pdf_at( offset ), offset )
+    }
+
+    fn pdf_at( &self, offset: usize ) -> f64 {
+        if self.func_int > 0.0 { self.func[offset] / self.func_int } else { 1.0 }
+    }
+}
+
+pub struct Distribution_2d {
+    conditional: Vec<Distribution_1d>,
+    marginal: Distribution_1d,
+}
+
+impl Distribution_2d {
+
+    // func holds nv rows of nu values each.
+    pub fn new( func: &[f64], nu: usize, nv: usize ) -> Self {
+
+        let conditional: Vec<Distribution_1d> =
+            ( 0..nv ).map( |v| Distribution_1d::new( &func[v * nu..( v + 1 ) * nu] )).collect();
+
+        let marginal_func: Vec<f64> = conditional.iter().map( |d| d.func_int() ).collect();
+        let marginal                = Distribution_1d::new( &marginal_func );
+
+        Self { conditional, marginal }
+    }
+
+    // Returns the sampled ( u, v ) point in [0, 1)^2 and its pdf.
+    pub fn sample_continuous( &self, u0: f64, u1: f64 ) -> ( f64, f64, f64 ) {
+
+        let ( d1, pdf1, v ) = self.marginal.sample_continuous( u1 );
+        let ( d0, pdf0, _ ) = self.conditional[v].sample_continuous( u0 );
+
+        ( d0, d1, pdf0 * pdf1 )
+    }
+
+    pub fn pdf( &self, u: f64, v: f64 ) -> f64 {
+
+        let nu = self.conditional[0].count();
+        let nv = self.marginal.count();
+
+        let iu = usize::min(( u * nu as f64 ) as usize, nu - 1 );
+        let iv = usize::min(( v * nv as f64 ) as usize, nv - 1 );
+
+        self.conditional[iv].pdf_at( iu ) * self.marginal.pdf_at( iv )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn pdf_1d_integrates_to_one() {
+
+        let d = Distribution_1d::new( &[ 1.0, 3.0, 0.0, 4.0 ] );
+        let n = d.count() as f64;
+
+        let integral: f64 = ( 0..d.count() ).map( |i| d.pdf_at( i ) / n ).sum();
+        assert!(( integral - 1.0 ).abs() < 1e-12 );
+
+        let ( x, pdf, offset ) = d.sample_continuous( 0.3 );
+        assert_eq!( offset, 1 );
+        assert!(( 0.25..0.5 ).contains( &x ));
+        assert!(( pdf - 1.5 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn zero_func_1d_falls_back_to_uniform() {
+
+        let d = Distribution_1d::new( &[ 0.0, 0.0, 0.0, 0.0 ] );
+
+        for &u in &[ 0.0, 0.3, 0.6, 0.99 ] {
+            let ( x, pdf, _ ) = d.sample_continuous( u );
+            assert!(( x - u ).abs() < 1e-12 );
+            assert_eq!( pdf, 1.0 );
+        }
+    }
+
+    #[test]
+    fn pdf_2d_integrates_to_one() {
+
+        let ( nu, nv ) = ( 4, 3 );
+        let func: Vec<f64> = ( 0..nu * nv ).map( |i| ( i % 5 ) as f64 ).collect();
+        let d = Distribution_2d::new( &func, nu, nv );
+
+        let mut integral = 0.0;
+        for v in 0..nv {
+            for u in 0..nu {
+                let ( cu, cv ) = (( u as f64 + 0.5 ) / nu as f64, ( v as f64 + 0.5 ) / nv as f64 );
+                integral += d.pdf( cu, cv ) / ( nu * nv ) as f64;
+            }
+        }
+        assert!(( integral - 1.0 ).abs() < 1e-12 );
+
+        let ( u, v, pdf ) = d.sample_continuous( 0.7, 0.4 );
+        assert!(( pdf - d.pdf( u, v )).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn zero_func_2d_falls_back_to_uniform() {
+
+        let d = Distribution_2d::new( &[ 0.0; 6 ], 3, 2 );
+
+        assert_eq!( d.pdf( 0.1, 0.9 ), 1.0 );
+        assert_eq!( d.sample_continuous( 0.25, 0.75 ).2, 1.0 );
+    }
+}
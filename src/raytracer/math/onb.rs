@@ -0,0 +1,51 @@
+use super::vec3f::*;
+
+// Orthonormal basis around a given normal, w being the normal itself.
+pub struct Onb {
+    axis: [ Vec3f; 3 ],
+}
+
+impl Onb {
+
+    pub fn new( n: Vec3f ) -> Self {
+
+        let w = n.normalize();
+        let a = if f64::abs( w.x() ) > 0.9 { Vec3f( 0.0, 1.0, 0.0 ) } else { Vec3f( 1.0, 0.0, 0.0 ) };
+        let v = cross( w, a ).normalize();
+        let u = cross( w, v );
+
+        Self { axis: [ u, v, w ] }
+    }
+
+    // Basis with u along the tangent, made perpendicular to n, so that it
+    // follows the surface. Falls back to new() when the tangent is missing or
+    // parallel to n.
+    pub fn from_tangent( n: Vec3f, tangent: Vec3f ) -> Self {
+
+        let w = n.normalize();
+        let t = tangent - dot( tangent, w ) * w;
+
+        if t.norm_sq() <= 1e-12 * tangent.norm_sq() {
+            return Self::new( n )
+        }
+
+        let u = t.normalize();
+        let v = cross( w, u );
+
+        Self { axis: [ u, v, w ] }
+    }
+
+    pub fn u( &self ) -> Vec3f { self.axis[0] }
+    pub fn v( &self ) -> Vec3f { self.axis[1] }
+    pub fn w( &self ) -> Vec3f { self.axis[2] }
+
+    // From local coordinates to world coordinates.
+    pub fn transform( &self, v: Vec3f ) -> Vec3f {
+        v.0 * self.axis[0] + v.1 * self.axis[1] + v.2 * self.axis[2]
+    }
+
+    // From world coordinates to local coordinates.
+    pub fn to_local( &self, v: Vec3f ) -> Vec3f {
+        Vec3f( dot( v, self.axis[0] ), dot( v, self.axis[1] ), dot( v, self.axis[2] ))
+    }
+}
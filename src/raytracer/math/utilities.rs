@@ -4,11 +4,19 @@ use super::PI;
 use super::random::{ Rand_generator
                    , random_f64 };
 
+use crate::raytracer::sampler::next_1d;
+
 pub const fn degrees_to_radians( degrees: f64 ) -> f64 {
     degrees * PI / 180.0
 }
 
-pub const fn random_double() -> f64 {
+// Within a pixel sample the numbers come from the camera's sampler.
+pub fn random_double() -> f64 {
+
+    if let Some( u ) = next_1d() {
+        return u
+    }
+
     static mut GENERATOR: Rand_generator = Rand_generator::new( 7892365412305211419 );
     
     unsafe {
@@ -16,6 +24,15 @@ pub const fn random_double() -> f64 {
     }
 }
 
-pub const fn random_double_interval( min: f64, max: f64 ) -> f64 {
+pub fn random_double_interval( min: f64, max: f64 ) -> f64 {
     min + ( max - min ) * random_double()
+}
+
+// Veach's power heuristic weight for a sample drawn with pdf_f.
+pub fn power_heuristic( pdf_f: f64, pdf_g: f64 ) -> f64 {
+
+    let f = pdf_f * pdf_f;
+    let g = pdf_g * pdf_g;
+
+    if f + g == 0.0 { 0.0 } else { f / ( f + g ) }
 }
\ No newline at end of file
@@ -11,8 +11,10 @@ use std::ops::SubAssign;
 
 use std::fmt;
 
+use super::PI;
 use super::utilities::{ random_double
                       , random_double_interval };
+use crate::raytracer::sampler::random_2d;
 
 #[derive( Clone, Copy )]
 pub struct Vec3f( pub f64, pub f64, pub f64 );
@@ -60,51 +62,46 @@ impl Vec3f {
              , self.0 * v.1 - self.1 * v.0 )
     }
 
-    pub const fn random() -> Vec3f {
+    pub fn random() -> Vec3f {
         Vec3f( random_double(), random_double(), random_double() )
     }
 
-    pub const fn random_vec_interval( min: f64, max: f64 ) -> Vec3f {
+    pub fn random_vec_interval( min: f64, max: f64 ) -> Vec3f {
         Vec3f( random_double_interval( min, max )
              , random_double_interval( min, max )
              , random_double_interval( min, max ))
     }
 
+    // Shirley and Chiu's concentric mapping, keeping the sampler's strata.
     pub fn random_in_unit_disk() -> Vec3f {
 
-        let mut p = Vec3f( random_double_interval( -1.0, 1.0 )
-                         , random_double_interval( -1.0, 1.0 ), 0.0 );
-        let mut normq = p.norm_sq();
+        let ( a, b ) = random_2d();
+        let ( a, b ) = ( 2.0 * a - 1.0, 2.0 * b - 1.0 );
 
-        while true {
-            if normq < 1.0 {
-                break;
-            } else {
-                p = Vec3f( random_double_interval( -1.0, 1.0 )
-                         , random_double_interval( -1.0, 1.0 ), 0.0 );
-                normq = p.norm_sq();
-            }
+        if a == 0.0 && b == 0.0 {
+            return Vec3f( 0.0, 0.0, 0.0 )
         }
 
-        p
+        let ( r, theta ) =
+            if a.abs() > b.abs() {
+                ( a, 0.25 * PI * b / a )
+            } else {
+                ( b, 0.5 * PI - 0.25 * PI * a / b )
+            };
+
+        Vec3f( r * f64::cos( theta ), r * f64::sin( theta ), 0.0 )
     }
 
+    // Uniform on the sphere from exactly two numbers, z being uniform in [-1, 1].
     pub fn random_unit_vector() -> Vec3f {
 
-        let mut p     = Vec3f::random_vec_interval( -1.0, 1.0 );
-        let mut normq = p.norm_sq();
+        let ( r1, r2 ) = random_2d();
 
-        while true {
-            if 1e-160 <= normq && normq <= 1.0 {
-                p = p / f64::sqrt( normq );
-                break;
-            } else {
-                p     = Vec3f::random_vec_interval( -1.0, 1.0 );
-                normq = p.norm_sq();
-            }
-        }
+        let z   = 1.0 - 2.0 * r1;
+        let r   = f64::sqrt( f64::max( 0.0, 1.0 - z * z ));
+        let phi = 2.0 * PI * r2;
 
-        p
+        Vec3f( r * f64::cos( phi ), r * f64::sin( phi ), z )
     }
 
     pub fn random_on_hemisphere( normal: &Vec3f ) -> Vec3f {
@@ -118,6 +115,19 @@ impl Vec3f {
         }
     }
 
+    // Cosine weighted direction around +z.
+    pub fn random_cosine_direction() -> Vec3f {
+
+        let ( r1, r2 ) = random_2d();
+
+        let phi = 2.0 * PI * r1;
+        let x   = f64::cos( phi ) * f64::sqrt( r2 );
+        let y   = f64::sin( phi ) * f64::sqrt( r2 );
+        let z   = f64::sqrt( 1.0 - r2 );
+
+        Vec3f( x, y, z )
+    }
+
     pub fn reflect( v: Vec3f, n: Vec3f ) -> Vec3f {
         v - 2.0 * dot(v, n) * n
     }
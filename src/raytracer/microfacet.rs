@@ -0,0 +1,292 @@
+use super::color::Color;
+use super::math::PI;
+use super::math::complex::Complex;
+use super::math::vec3f::*;
+
+// Trowbridge-Reitz (GGX) distribution, in a local frame where z is the normal
+// and x the tangent.
+pub struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl Ggx {
+
+    pub fn new( alpha_x: f64, alpha_y: f64 ) -> Self {
+        Self { alpha_x: f64::max( alpha_x, 1e-4 ), alpha_y: f64::max( alpha_y, 1e-4 ) }
+    }
+
+    // Perceptual roughness to alpha, as in Disney's BRDF.
+    pub fn roughness_to_alpha( roughness: f64 ) -> f64 {
+        roughness * roughness
+    }
+
+    // Below this the lobe is sharper than anything we can sample, treat it as a mirror.
+    pub fn effectively_smooth( &self ) -> bool {
+        f64::max( self.alpha_x, self.alpha_y ) < 1e-3
+    }
+
+    pub fn d( &self, wm: Vec3f ) -> f64 {
+
+        if wm.z() <= 0.0 { return 0.0 }
+
+        let x = wm.x() / self.alpha_x;
+        let y = wm.y() / self.alpha_y;
+        let e = x * x + y * y + wm.z() * wm.z();
+
+        1.0 / ( PI * self.alpha_x * self.alpha_y * e * e )
+    }
+
+    pub fn lambda( &self, w: Vec3f ) -> f64 {
+
+        let cos2 = w.z() * w.z();
+        if cos2 == 0.0 { return 0.0 }
+
+        let ax = w.x() * self.alpha_x;
+        let ay = w.y() * self.alpha_y;
+
+        0.5 * ( -1.0 + f64::sqrt( 1.0 + ( ax * ax + ay * ay ) / cos2 ))
+    }
+
+    pub fn g1( &self, w: Vec3f ) -> f64 {
+        1.0 / ( 1.0 + self.lambda( w ))
+    }
+
+    pub fn g( &self, wo: Vec3f, wi: Vec3f ) -> f64 {
+        1.0 / ( 1.0 + self.lambda( wo ) + self.lambda( wi ))
+    }
+
+    // Density of visible normals as seen from wo.
+    pub fn d_visible( &self, wo: Vec3f, wm: Vec3f ) -> f64 {
+
+        let cos_o = f64::abs( wo.z() );
+        if cos_o == 0.0 { return 0.0 }
+
+        self.g1( wo ) * f64::max( dot( wo, wm ), 0.0 ) * self.d( wm ) / cos_o
+    }
+
+    // Heitz, "Sampling the GGX Distribution of Visible Normals", 2018.
+    pub fn sample_wm( &self, wo: Vec3f, u1: f64, u2: f64 ) -> Vec3f {
+
+        let wo = if wo.z() < 0.0 { -wo } else { wo };
+        let vh = Vec3f( self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z() ).normalize();
+
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1    = if lensq > 0.0 { Vec3f( -vh.y(), vh.x(), 0.0 ) / f64::sqrt( lensq ) } else { Vec3f( 1.0, 0.0, 0.0 ) };
+        let t2    = cross( vh, t1 );
+
+        let r   = f64::sqrt( u1 );
+        let phi = 2.0 * PI * u2;
+        let p1  = r * f64::cos( phi );
+        let p2  = r * f64::sin( phi );
+        let s   = 0.5 * ( 1.0 + vh.z() );
+        let p2  = ( 1.0 - s ) * f64::sqrt( 1.0 - p1 * p1 ) + s * p2;
+
+        let nh = p1 * t1 + p2 * t2 + f64::sqrt( f64::max( 0.0, 1.0 - p1 * p1 - p2 * p2 )) * vh;
+
+        Vec3f( self.alpha_x * nh.x(), self.alpha_y * nh.y(), f64::max( 1e-6, nh.z() )).normalize()
+    }
+}
+
+// Unpolarized Fresnel reflectance for a conductor of complex IOR eta + i k.
+pub fn fresnel_conductor( cos_i: f64, eta: f64, k: f64 ) -> f64 {
+
+    let cos_i  = f64::clamp( cos_i, 0.0, 1.0 );
+    let cos2   = cos_i * cos_i;
+    let sin2   = 1.0 - cos2;
+    let eta2   = eta * eta;
+    let k2     = k * k;
+
+    let t0       = eta2 - k2 - sin2;
+    let a2plusb2 = f64::sqrt( t0 * t0 + 4.0 * eta2 * k2 );
+    let t1       = a2plusb2 + cos2;
+    let a        = f64::sqrt( f64::max( 0.5 * ( a2plusb2 + t0 ), 0.0 ));
+    let t2       = 2.0 * cos_i * a;
+    let rs       = ( t1 - t2 ) / ( t1 + t2 );
+
+    let t3 = cos2 * a2plusb2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * ( t3 - t4 ) / ( t3 + t4 );
+
+    0.5 * ( rp + rs )
+}
+
+pub fn fresnel_conductor_rgb( cos_i: f64, eta: &Color, k: &Color ) -> Color {
+    Color( fresnel_conductor( cos_i, eta.0, k.0 )
+         , fresnel_conductor( cos_i, eta.1, k.1 )
+         , fresnel_conductor( cos_i, eta.2, k.2 ))
+}
+
+// Unpolarized Fresnel reflectance between dielectrics, eta being eta_t / eta_i.
+pub fn fresnel_dielectric( cos_i: f64, eta: f64 ) -> f64 {
+
+    let ( cos_i, eta ) =
+        if cos_i < 0.0 { ( -cos_i, 1.0 / eta ) } else { ( f64::min( cos_i, 1.0 ), eta ) };
+
+    let sin2_t = ( 1.0 - cos_i * cos_i ) / ( eta * eta );
+    if sin2_t >= 1.0 {
+        return 1.0
+    }
+
+    let cos_t  = f64::sqrt( 1.0 - sin2_t );
+    let r_parl = ( eta * cos_i - cos_t ) / ( eta * cos_i + cos_t );
+    let r_perp = ( cos_i - eta * cos_t ) / ( cos_i + eta * cos_t );
+
+    0.5 * ( r_parl * r_parl + r_perp * r_perp )
+}
+
+// Amplitude reflection coefficients, s and p polarized, from medium a into
+// medium b. Also returns the cosine of the refracted angle.
+fn fresnel_amplitudes( cos_a: Complex, eta_a: Complex, eta_b: Complex ) -> ( Complex, Complex, Complex ) {
+
+    let one   = Complex::real( 1.0 );
+    let ratio = eta_a / eta_b;
+    let cos_b = ( one - ratio * ratio * ( one - cos_a * cos_a )).sqrt();
+
+    let r_s = ( eta_a * cos_a - eta_b * cos_b ) / ( eta_a * cos_a + eta_b * cos_b );
+    let r_p = ( eta_b * cos_a - eta_a * cos_b ) / ( eta_b * cos_a + eta_a * cos_b );
+
+    ( r_s, r_p, cos_b )
+}
+
+// Reflectance of a dielectric film of the given thickness, lying between the
+// incident medium and a substrate of complex IOR eta_t + i k_t, at the
+// wavelength lambda. Thickness and wavelength are both in nm.
+pub fn fresnel_thin_film( cos_i: f64, eta_i: f64, film_ior: f64, thickness: f64, eta_t: f64, k_t: f64, lambda: f64 ) -> f64 {
+
+    let cos_i = Complex::real( f64::clamp( cos_i, 0.0, 1.0 ));
+    let eta_i = Complex::real( eta_i );
+    let eta_f = Complex::real( film_ior );
+    let eta_t = Complex::new( eta_t, k_t );
+
+    let ( r12_s, r12_p, cos_f ) = fresnel_amplitudes( cos_i, eta_i, eta_f );
+    let ( r23_s, r23_p, _ )     = fresnel_amplitudes( cos_f, eta_f, eta_t );
+
+    // Round trip phase through the film.
+    let phase = ( 4.0 * PI * thickness / lambda ) * ( eta_f * cos_f );
+    let shift = phase.exp_i();
+
+    // Airy summation of the multiple reflections inside the film.
+    let airy = | r12: Complex, r23: Complex | {
+        (( r12 + r23 * shift ) / ( Complex::real( 1.0 ) + r12 * r23 * shift )).norm_sqr()
+    };
+
+    0.5 * ( airy( r12_s, r23_s ) + airy( r12_p, r23_p ))
+}
+
+// Refracts wo, pointing away from the surface on the same side as n. Returns
+// false on total internal reflection.
+pub fn refract_through( wo: Vec3f, n: Vec3f, eta: f64 ) -> ( bool, Vec3f ) {
+
+    let cos_i  = dot( n, wo );
+    let sin2_i = f64::max( 0.0, 1.0 - cos_i * cos_i );
+    let sin2_t = sin2_i / ( eta * eta );
+
+    if sin2_t >= 1.0 {
+        return ( false, Vec3f( 0.0, 0.0, 0.0 ))
+    }
+
+    let cos_t = f64::sqrt( 1.0 - sin2_t );
+
+    ( true, -wo / eta + ( cos_i / eta - cos_t ) * n )
+}
+
+// Generalized half vector of a transmission pair, on the wo side.
+fn transmission_half_vector( wo: Vec3f, wi: Vec3f, eta: f64 ) -> Vec3f {
+    let wm = ( wo + eta * wi ).normalize();
+    if wm.z() < 0.0 { -wm } else { wm }
+}
+
+// BSDF times |cos| of a rough dielectric interface, in the local frame with wo
+// above the surface, both for reflection and transmission.
+pub fn dielectric_eval( distribution: &Ggx, wo: Vec3f, wi: Vec3f, eta: f64 ) -> f64 {
+
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return 0.0
+    }
+
+    if wi.z() > 0.0 {
+        let wm = ( wo + wi ).normalize();
+        return distribution.d( wm ) * distribution.g( wo, wi ) * fresnel_dielectric( dot( wo, wm ), eta )
+             / ( 4.0 * wo.z() )
+    }
+
+    let wm = transmission_half_vector( wo, wi, eta );
+    if dot( wo, wm ) <= 0.0 || dot( wi, wm ) >= 0.0 { return 0.0 }
+
+    let denom = dot( wi, wm ) + dot( wo, wm ) / eta;
+
+    distribution.d( wm ) * distribution.g( wo, wi ) * ( 1.0 - fresnel_dielectric( dot( wo, wm ), eta ))
+  * f64::abs( dot( wi, wm ) * dot( wo, wm ) / ( wo.z() * denom * denom ))
+}
+
+// Pdf of picking wi by sampling a visible normal, then reflecting with
+// probability F and refracting otherwise.
+pub fn dielectric_pdf( distribution: &Ggx, wo: Vec3f, wi: Vec3f, eta: f64 ) -> f64 {
+
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return 0.0
+    }
+
+    if wi.z() > 0.0 {
+        let wm = ( wo + wi ).normalize();
+        return distribution.d_visible( wo, wm ) * fresnel_dielectric( dot( wo, wm ), eta )
+             / ( 4.0 * dot( wo, wm ))
+    }
+
+    let wm = transmission_half_vector( wo, wi, eta );
+    if dot( wo, wm ) <= 0.0 || dot( wi, wm ) >= 0.0 { return 0.0 }
+
+    let denom = dot( wi, wm ) + dot( wo, wm ) / eta;
+
+    distribution.d_visible( wo, wm ) * ( 1.0 - fresnel_dielectric( dot( wo, wm ), eta ))
+  * f64::abs( dot( wi, wm )) / ( denom * denom )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn conductor_at_normal_incidence() {
+
+        let ( eta, k ) = ( 0.2, 3.9 );
+        let expected   = (( eta - 1.0 ) * ( eta - 1.0 ) + k * k ) / (( eta + 1.0 ) * ( eta + 1.0 ) + k * k );
+
+        assert!(( fresnel_conductor( 1.0, eta, k ) - expected ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn conductor_at_grazing_incidence() {
+        assert!(( fresnel_conductor( 0.0, 0.2, 3.9 ) - 1.0 ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn conductor_without_absorption_matches_dielectric() {
+        let expected = f64::powi(( 1.5 - 1.0 ) / ( 1.5 + 1.0 ), 2 );
+        assert!(( fresnel_conductor( 1.0, 1.5, 0.0 ) - expected ).abs() < 1e-12 );
+    }
+
+    #[test]
+    fn thin_film_of_zero_thickness_is_bare_fresnel() {
+
+        for &cos_i in &[ 1.0, 0.7, 0.3 ] {
+            let conductor = fresnel_thin_film( cos_i, 1.0, 1.38, 0.0, 0.2, 3.9, 550.0 );
+            let glass     = fresnel_thin_film( cos_i, 1.0, 1.38, 0.0, 1.5, 0.0, 550.0 );
+
+            assert!(( conductor - fresnel_conductor( cos_i, 0.2, 3.9 )).abs() < 1e-9 );
+            assert!(( glass - fresnel_dielectric( cos_i, 1.5 )).abs() < 1e-9 );
+        }
+    }
+
+    #[test]
+    fn quarter_wave_film_at_normal_incidence() {
+
+        let ( n_f, n_t, lambda ) = ( 1.38, 1.5, 550.0 );
+        let expected = f64::powi(( n_t - n_f * n_f ) / ( n_t + n_f * n_f ), 2 );
+
+        let r = fresnel_thin_film( 1.0, 1.0, n_f, lambda / ( 4.0 * n_f ), n_t, 0.0, lambda );
+        assert!(( r - expected ).abs() < 1e-9 );
+    }
+}
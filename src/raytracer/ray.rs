@@ -1,21 +1,107 @@
 
+use super::color::Color;
 use super::math::vec3f::*;
 
+// A dielectric medium the ray is travelling through.
+#[derive( Clone, Copy )]
+pub struct Medium {
+    pub ior: f64,
+    pub sigma_a: Color,
+}
+
+const MAX_MEDIA: usize = 4;
+
+// Stack of nested media, the innermost one on top. Empty means air.
+#[derive( Clone, Copy )]
+pub struct Media {
+    stack: [ Medium; MAX_MEDIA ],
+    len: usize,
+}
+
+impl Media {
+
+    pub fn new() -> Self {
+        let air = Medium { ior: 1.0, sigma_a: Color( 0.0, 0.0, 0.0 ) };
+        Self { stack: [ air; MAX_MEDIA ], len: 0 }
+    }
+
+    pub fn ior( &self ) -> f64 {
+        if self.len > 0 { self.stack[self.len - 1].ior } else { 1.0 }
+    }
+
+    // IOR of the medium we get back into when leaving the innermost one.
+    pub fn outer_ior( &self ) -> f64 {
+        if self.len > 1 { self.stack[self.len - 2].ior } else { 1.0 }
+    }
+
+    pub fn push( &mut self, medium: Medium ) {
+        if self.len < MAX_MEDIA {
+            self.len += 1;
+        }
+        self.stack[self.len - 1] = medium;
+    }
+
+    pub fn pop( &mut self ) {
+        self.len = self.len.saturating_sub( 1 );
+    }
+
+    // Beer-Lambert absorption along distance in the innermost medium.
+    pub fn transmittance( &self, distance: f64 ) -> Color {
+
+        if self.len == 0 {
+            return Color( 1.0, 1.0, 1.0 )
+        }
+
+        let sigma_a = self.stack[self.len - 1].sigma_a;
+        Color( f64::exp( -sigma_a.0 * distance )
+             , f64::exp( -sigma_a.1 * distance )
+             , f64::exp( -sigma_a.2 * distance ))
+    }
+}
+
+// wavelength is the hero wavelength in nm for spectral rendering, 0 for plain
+// RGB rays. hero_only is set once the path dropped its secondary wavelengths.
+#[derive( Clone, Copy )]
 pub struct Ray {
     orig: Point3,
     dir: Vec3f,
+    media: Media,
+    wavelength: f64,
+    hero_only: bool,
 }
 #[allow( non_snake_case )]
 pub fn Ray( orig: Point3, dir: Vec3f ) -> Ray {
-    Ray { orig, dir }
+    Ray { orig, dir, media: Media::new(), wavelength: 0.0, hero_only: false }
 }
 
 impl Ray {
 
-    pub fn    origin( &self ) -> Point3 { self.orig }
-    pub fn direction( &self ) ->  Vec3f { self.dir }
+    pub fn     origin( &self ) -> Point3 { self.orig }
+    pub fn  direction( &self ) ->  Vec3f { self.dir }
+    pub fn      media( &self ) -> &Media { &self.media }
+    pub fn wavelength( &self ) ->    f64 { self.wavelength }
+    pub fn  hero_only( &self ) ->   bool { self.hero_only }
+
+    pub fn media_mut( &mut self ) -> &mut Media { &mut self.media }
 
     pub fn at( &self, t: f64 ) -> Point3 {
         self.orig + t * self.dir
     }
+
+    pub fn with_wavelength( &self, wavelength: f64 ) -> Ray {
+        Ray { wavelength, ..*self }
+    }
+
+    pub fn with_origin( &self, orig: Point3 ) -> Ray {
+        Ray { orig, ..*self }
+    }
+
+    pub fn terminate_secondary( &self ) -> Ray {
+        Ray { hero_only: true, ..*self }
+    }
+
+    // New ray continuing this path, so it keeps its media and wavelengths.
+    pub fn spawn( &self, orig: Point3, dir: Vec3f ) -> Ray {
+        Ray { orig, dir, ..*self }
+    }
 }
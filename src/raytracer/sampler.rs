@@ -0,0 +1,469 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use super::math::utilities::random_double;
+
+// Numbers for the sample index out of count in pixel i, j, one per dimension.
+// Dimensions 2k and 2k + 1 make a well distributed pair, which is how the
+// camera draws its pixel offset and lens position, and how materials and
+// lights draw their two numbers at every bounce.
+pub trait Sampler: Send + Sync {
+    fn sample( &self, i: i32, j: i32, index: u32, count: u32, dim: u32 ) -> f64;
+}
+
+// Every number on its own, hashed so renders are repeatable.
+pub struct Independent_sampler;
+
+impl Sampler for Independent_sampler {
+    fn sample( &self, i: i32, j: i32, index: u32, count: u32, dim: u32 ) -> f64 {
+        to_unit( hash_combine( hash_combine( pixel_seed( i, j ), index ), dim ))
+    }
+}
+
+// Jittered grid over each pair of dimensions, the cells shuffled differently
+// for every pair so the pairs don't correlate. Indices past the grid's cells,
+// which can't happen when count is known, get independent numbers.
+pub struct Stratified_sampler;
+
+impl Sampler for Stratified_sampler {
+
+    fn sample( &self, i: i32, j: i32, index: u32, count: u32, dim: u32 ) -> f64 {
+
+        let seed   = hash_combine( pixel_seed( i, j ), dim / 2 );
+        let jitter = to_unit( hash_combine( hash_combine( seed, index ), dim ));
+
+        let nx    = f64::ceil( f64::sqrt( count.max( 1 ) as f64 )) as u32;
+        let ny    = count.max( 1 ).div_ceil( nx );
+        let cells = nx * ny;
+
+        if index >= cells {
+            return jitter
+        }
+
+        let cell = permute( index, cells, seed );
+
+        if dim % 2 == 0 {
+            (( cell % nx ) as f64 + jitter ) / nx as f64
+        } else {
+            (( cell / nx ) as f64 + jitter ) / ny as f64
+        }
+    }
+}
+
+// Halton points, Owen scrambled per pixel so the large bases' first points
+// don't all bunch up near zero. Beyond the prime table the numbers are
+// independent.
+pub struct Halton_sampler {
+    primes: Vec<u32>,
+}
+
+impl Halton_sampler {
+
+    pub fn new() -> Self {
+
+        let mut primes = Vec::new();
+        let mut n      = 2;
+
+        while primes.len() < 64 {
+            if primes.iter().all( |p| n % p != 0 ) {
+                primes.push( n );
+            }
+            n += 1;
+        }
+
+        Halton_sampler { primes }
+    }
+}
+
+impl Sampler for Halton_sampler {
+
+    fn sample( &self, i: i32, j: i32, index: u32, count: u32, dim: u32 ) -> f64 {
+
+        let seed = hash_combine( pixel_seed( i, j ), dim );
+
+        if dim as usize >= self.primes.len() {
+            return to_unit( hash_combine( seed, index ))
+        }
+
+        owen_radical_inverse( self.primes[dim as usize], index, seed )
+    }
+}
+
+// Sobol's first two dimensions padded to any number of them, each pair with
+// its own shuffle of the indices and Owen scrambling, after Burley's
+// "Practical Hash-based Owen Scrambling".
+pub struct Sobol_sampler;
+
+impl Sampler for Sobol_sampler {
+    fn sample( &self, i: i32, j: i32, index: u32, count: u32, dim: u32 ) -> f64 {
+        to_unit( owen_sobol( index, dim, pixel_seed( i, j )))
+    }
+}
+
+// The same Owen scrambled Sobol points in every pixel, toroidally shifted by a
+// blue noise mask as in Georgiev and Fajardo's "Blue-noise Dithered Sampling",
+// so what error is left looks like fine grain rather than blotches. Each
+// dimension reads the mask at its own offset.
+pub struct Blue_noise_sampler {
+    mask: Vec<f64>,
+}
+
+const MASK_SIZE: usize = 64;
+
+impl Blue_noise_sampler {
+
+    pub fn new() -> Self {
+        Blue_noise_sampler { mask: void_and_cluster() }
+    }
+}
+
+impl Sampler for Blue_noise_sampler {
+
+    fn sample( &self, i: i32, j: i32, index: u32, count: u32, dim: u32 ) -> f64 {
+
+        let offset = hash( dim ) as usize;
+        let x      = ( i.rem_euclid( MASK_SIZE as i32 ) as usize + offset ) % MASK_SIZE;
+        let y      = ( j.rem_euclid( MASK_SIZE as i32 ) as usize + ( offset >> 8 )) % MASK_SIZE;
+
+        let u = to_unit( owen_sobol( index, dim, 0x5bd1e995 )) + self.mask[y * MASK_SIZE + x];
+
+        if u >= 1.0 { u - 1.0 } else { u }
+    }
+}
+
+// Where each part of a pixel sample draws its numbers, so a dimension means the
+// same thing in every sample however many numbers came before it. The time
+// dimension is kept for motion blur, rays carrying no time yet. Every bounce
+// gets its own block after the camera's.
+pub const FILM_DIMENSION: u32       = 0;
+pub const LENS_DIMENSION: u32       = 2;
+pub const TIME_DIMENSION: u32       = 6;
+pub const WAVELENGTH_DIMENSION: u32 = 7;
+pub const BOUNCE_DIMENSION: u32     = 8;
+
+pub const LENS_DIMENSIONS: u32   = 4;
+pub const BOUNCE_DIMENSIONS: u32 = 16;
+
+// The pixel sample being traced on this thread, random_double handing out its
+// dimensions one after the other. Past the end of the current block, say in a
+// rejection loop, the numbers are independent ones well away from any block.
+struct Active_sample {
+    sampler: Arc<dyn Sampler>,
+    i: i32,
+    j: i32,
+    index: u32,
+    count: u32,
+    dim: u32,
+    end: u32,
+    spill: u32,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Option<Active_sample>> = const { RefCell::new( None ) };
+}
+
+pub fn start_pixel_sample( sampler: &Arc<dyn Sampler>, i: i32, j: i32, index: u32, count: u32 ) {
+    ACTIVE.with( |active| {
+        *active.borrow_mut() = Some( Active_sample { sampler: sampler.clone(), i, j, index, count, dim: 0, end: u32::MAX, spill: 0 } );
+    });
+}
+
+// Carries on from dimension first, with count of them to draw from.
+pub fn start_dimensions( first: u32, count: u32 ) {
+    ACTIVE.with( |active| {
+        if let Some( s ) = active.borrow_mut().as_mut() {
+            s.dim = first;
+            s.end = first.saturating_add( count );
+        }
+    });
+}
+
+// Block of the bounce-th bounce along a path, the camera ray's hit being 0.
+pub fn start_bounce( bounce: u32 ) {
+    start_dimensions( BOUNCE_DIMENSION.saturating_add( bounce.saturating_mul( BOUNCE_DIMENSIONS )), BOUNCE_DIMENSIONS );
+}
+
+pub fn end_pixel_samples() {
+    ACTIVE.with( |active| *active.borrow_mut() = None );
+}
+
+// Next dimension of the active pixel sample, if any.
+pub fn next_1d() -> Option<f64> {
+    ACTIVE.with( |active| {
+        active.borrow_mut().as_mut().map( |s| {
+
+            if s.dim >= s.end {
+                let dim  = u32::MAX - s.spill;
+                s.spill += 1;
+                return to_unit( hash_combine( hash_combine( pixel_seed( s.i, s.j ), s.index ), dim ))
+            }
+
+            let u  = s.sampler.sample( s.i, s.j, s.index, s.count, s.dim );
+            s.dim += 1;
+            u
+        })
+    })
+}
+
+// Two numbers meant to be used together, starting on a pair's first dimension.
+pub fn random_2d() -> ( f64, f64 ) {
+
+    ACTIVE.with( |active| {
+        if let Some( s ) = active.borrow_mut().as_mut() {
+            s.dim += s.dim % 2;
+        }
+    });
+
+    ( random_double(), random_double() )
+}
+
+fn owen_sobol( index: u32, dim: u32, seed: u32 ) -> u32 {
+
+    let seed  = hash_combine( seed, dim / 2 );
+    let index = nested_uniform_scramble( index, seed );
+
+    let x = if dim % 2 == 0 { index.reverse_bits() } else { sobol_second( index ) };
+
+    nested_uniform_scramble( x, hash_combine( seed, 1 + dim % 2 ))
+}
+
+// Sobol's second dimension, from the primitive polynomial x + 1.
+fn sobol_second( index: u32 ) -> u32 {
+
+    let mut index = index;
+    let mut v     = 1u32 << 31;
+    let mut x     = 0;
+
+    while index != 0 {
+        if index & 1 != 0 { x ^= v; }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+
+    x
+}
+
+fn laine_karras_permutation( x: u32, seed: u32 ) -> u32 {
+
+    let mut x = x.wrapping_add( seed );
+
+    x ^= x.wrapping_mul( 0x6c50b47c );
+    x ^= x.wrapping_mul( 0xb82f1e52 );
+    x ^= x.wrapping_mul( 0xc7afe638 );
+    x ^= x.wrapping_mul( 0x8d22f6e6 );
+
+    x
+}
+
+fn nested_uniform_scramble( x: u32, seed: u32 ) -> u32 {
+    laine_karras_permutation( x.reverse_bits(), seed ).reverse_bits()
+}
+
+// Each digit permuted depending on the digits before it, going on past the
+// index's own digits until the precision runs out.
+fn owen_radical_inverse( base: u32, index: u32, seed: u32 ) -> f64 {
+
+    let inv_base = 1.0 / base as f64;
+
+    let mut index    = index;
+    let mut prefix   = seed;
+    let mut reversed = 0.0;
+    let mut scale    = inv_base;
+
+    while scale > 1e-12 {
+        let digit = index % base;
+
+        reversed += permute( digit, base, prefix ) as f64 * scale;
+        prefix    = hash_combine( prefix, digit );
+        index    /= base;
+        scale    *= inv_base;
+    }
+
+    f64::min( reversed, 1.0 - f64::EPSILON )
+}
+
+// Kensler's hashed permutation of 0..length, from "Correlated Multi-Jittered
+// Sampling".
+fn permute( i: u32, length: u32, seed: u32 ) -> u32 {
+
+    let mut w = length.max( 1 ) - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let mut i = i;
+
+    loop {
+        i ^= seed;
+        i  = i.wrapping_mul( 0xe170893d );
+        i ^= seed >> 16;
+        i ^= ( i & w ) >> 4;
+        i ^= seed >> 8;
+        i  = i.wrapping_mul( 0x0929eb3f );
+        i ^= seed >> 23;
+        i ^= ( i & w ) >> 1;
+        i  = i.wrapping_mul( 1 | seed >> 27 );
+        i  = i.wrapping_mul( 0x6935fa69 );
+        i ^= ( i & w ) >> 11;
+        i  = i.wrapping_mul( 0x74dcb303 );
+        i ^= ( i & w ) >> 2;
+        i  = i.wrapping_mul( 0x9e501cc3 );
+        i ^= ( i & w ) >> 2;
+        i  = i.wrapping_mul( 0xc860a3df );
+        i &= w;
+        i ^= i >> 5;
+
+        if i < length.max( 1 ) { break; }
+    }
+
+    ( i.wrapping_add( seed )) % length.max( 1 )
+}
+
+// Ulichney's void and cluster method, ranks of a 64 by 64 tile mapped to
+// evenly spread values in [0, 1).
+fn void_and_cluster() -> Vec<f64> {
+
+    let n     = MASK_SIZE * MASK_SIZE;
+    let sigma = 1.5;
+
+    // Gaussian of the toroidal distance, indexed by the offset.
+    let kernel: Vec<f64> = ( 0..n ).map( |k| {
+        let dx = usize::min( k % MASK_SIZE, MASK_SIZE - k % MASK_SIZE ) as f64;
+        let dy = usize::min( k / MASK_SIZE, MASK_SIZE - k / MASK_SIZE ) as f64;
+        f64::exp( -( dx * dx + dy * dy ) / ( 2.0 * sigma * sigma ))
+    }).collect();
+
+    let toggle = | pattern: &mut Vec<bool>, energy: &mut Vec<f64>, at: usize | {
+        pattern[at] = !pattern[at];
+        let sign    = if pattern[at] { 1.0 } else { -1.0 };
+        let ( ax, ay ) = ( at % MASK_SIZE, at / MASK_SIZE );
+        for ( k, e ) in energy.iter_mut().enumerate() {
+            let dx = ( k % MASK_SIZE + MASK_SIZE - ax ) % MASK_SIZE;
+            let dy = ( k / MASK_SIZE + MASK_SIZE - ay ) % MASK_SIZE;
+            *e += sign * kernel[dy * MASK_SIZE + dx];
+        }
+    };
+
+    // Tightest cluster among the set pixels, or the largest void among the rest.
+    let extreme = | pattern: &Vec<bool>, energy: &Vec<f64>, set: bool | -> usize {
+        let candidates = ( 0..n ).filter( |&k| pattern[k] == set );
+        if set {
+            candidates.max_by( |&a, &b| energy[a].total_cmp( &energy[b] )).unwrap()
+        } else {
+            candidates.min_by( |&a, &b| energy[a].total_cmp( &energy[b] )).unwrap()
+        }
+    };
+
+    let mut pattern = vec![ false; n ];
+    let mut energy  = vec![ 0.0; n ];
+
+    // A tenth of the pixels at random, then moved from clusters to voids until
+    // they're evenly spread.
+    let mut seed = 1;
+    let mut ones = 0;
+    while ones < n / 10 {
+        seed = hash( seed );
+        let at = seed as usize % n;
+        if !pattern[at] {
+            toggle( &mut pattern, &mut energy, at );
+            ones += 1;
+        }
+    }
+
+    loop {
+        let cluster = extreme( &pattern, &energy, true );
+        toggle( &mut pattern, &mut energy, cluster );
+
+        let void = extreme( &pattern, &energy, false );
+        toggle( &mut pattern, &mut energy, void );
+
+        if void == cluster { break; }
+    }
+
+    let mut ranks = vec![ 0; n ];
+
+    // Ranks below the initial pattern's count, removing clusters first.
+    let ( mut removing, mut removed_energy ) = ( pattern.clone(), energy.clone() );
+    for rank in ( 0..ones ).rev() {
+        let cluster = extreme( &removing, &removed_energy, true );
+        toggle( &mut removing, &mut removed_energy, cluster );
+        ranks[cluster] = rank;
+    }
+
+    // And the rest, filling voids.
+    for rank in ones..n {
+        let void = extreme( &pattern, &energy, false );
+        toggle( &mut pattern, &mut energy, void );
+        ranks[void] = rank;
+    }
+
+    ranks.iter().map( |&rank| ( rank as f64 + 0.5 ) / n as f64 ).collect()
+}
+
+fn hash( x: u32 ) -> u32 {
+
+    let mut x = x;
+
+    x ^= x >> 16;
+    x  = x.wrapping_mul( 0x7feb352d );
+    x ^= x >> 15;
+    x  = x.wrapping_mul( 0x846ca68b );
+    x ^= x >> 16;
+
+    x
+}
+
+fn hash_combine( seed: u32, value: u32 ) -> u32 {
+    hash( seed ^ value.wrapping_add( 0x9e3779b9 ).wrapping_add( seed << 6 ).wrapping_add( seed >> 2 ))
+}
+
+fn pixel_seed( i: i32, j: i32 ) -> u32 {
+    hash_combine( hash( i as u32 ), j as u32 )
+}
+
+fn to_unit( x: u32 ) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn assert_in_unit_interval( sampler: &dyn Sampler ) {
+        for ( i, j ) in [ ( 0, 0 ), ( 3, 7 ), ( -1, 12 ), ( 511, 255 ) ] {
+            for count in [ 1, 7, 16, 64 ] {
+                for index in 0..count + 2 {
+                    for dim in 0..40 {
+                        let x = sampler.sample( i, j, index, count, dim );
+                        assert!(( 0.0..1.0 ).contains( &x ), "{} at pixel {} {}, sample {} of {}, dimension {}", x, i, j, index, count, dim );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_stay_in_the_unit_interval() {
+        assert_in_unit_interval( &Independent_sampler );
+        assert_in_unit_interval( &Stratified_sampler );
+        assert_in_unit_interval( &Halton_sampler::new() );
+        assert_in_unit_interval( &Sobol_sampler );
+        assert_in_unit_interval( &Blue_noise_sampler::new() );
+    }
+
+    #[test]
+    fn stratified_pairs_cover_every_cell() {
+
+        let mut cells = [ false; 16 ];
+        for index in 0..16 {
+            let x = Stratified_sampler.sample( 2, 5, index, 16, 0 );
+            let y = Stratified_sampler.sample( 2, 5, index, 16, 1 );
+            cells[( y * 4.0 ) as usize * 4 + ( x * 4.0 ) as usize] = true;
+        }
+
+        assert!( cells.iter().all( |c| *c ));
+    }
+}
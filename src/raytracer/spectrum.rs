@@ -0,0 +1,371 @@
+use std::sync::OnceLock;
+
+use super::color::{ Color, luminance, xyz_to_rgb };
+use super::math::utilities::random_double_interval;
+use super::math::vec3f::*;
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 720.0;
+
+// Wavelength, in nm, that non spectral rendering uses for wavelength dependent
+// quantities like IORs. The sodium d-line IORs are usually quoted at.
+pub const LAMBDA_REFERENCE: f64 = 587.6;
+
+pub fn sample_wavelength() -> f64 {
+    random_double_interval( LAMBDA_MIN, LAMBDA_MAX )
+}
+
+// Spectral paths carry three wavelengths, one per Color channel: the sampled
+// hero one and two more evenly spread over the range (Wilkie et al., "Hero
+// Wavelength Spectral Sampling"). Each one alone is uniformly distributed.
+pub fn hero_wavelengths( lambda: f64 ) -> [ f64; 3 ] {
+
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let shift = | i: f64 | LAMBDA_MIN + ( lambda - LAMBDA_MIN + i * range / 3.0 ) % range;
+
+    [ lambda, shift( 1.0 ), shift( 2.0 ) ]
+}
+
+// Values of a spectrum at the wavelengths of a path with hero wavelength lambda.
+pub fn at_hero_wavelengths( spectrum: impl Fn( f64 ) -> f64, lambda: f64 ) -> Color {
+    let wavelengths = hero_wavelengths( lambda );
+    Color( spectrum( wavelengths[0] ), spectrum( wavelengths[1] ), spectrum( wavelengths[2] ))
+}
+
+// Weight dropping the secondary wavelengths of a path, once it went through an
+// event that only makes sense for the hero one, like dispersive refraction.
+pub fn hero_only_weight() -> Color {
+    Color( 3.0, 0.0, 0.0 )
+}
+
+// CIE 1931 color matching functions, Wyman, Sloan and Shirley's multi-lobe fit.
+pub fn cie_xyz( lambda: f64 ) -> Vec3f {
+
+    let g = | mu: f64, sigma1: f64, sigma2: f64 | {
+        let t = ( lambda - mu ) / if lambda < mu { sigma1 } else { sigma2 };
+        f64::exp( -0.5 * t * t )
+    };
+
+    Vec3f( 1.056 * g( 599.8, 37.9, 31.0 ) + 0.362 * g( 442.0, 16.0, 26.7 ) - 0.065 * g( 501.1, 20.4, 26.2 )
+         , 0.821 * g( 568.8, 46.9, 40.5 ) + 0.286 * g( 530.9, 16.3, 31.1 )
+         , 1.217 * g( 437.0, 11.8, 36.0 ) + 0.681 * g( 459.0, 26.0, 13.8 ))
+}
+
+// RGB of the constant 1 spectrum, used to keep white white.
+fn spectral_white() -> Color {
+
+    static WHITE: OnceLock<Color> = OnceLock::new();
+
+    *WHITE.get_or_init( || {
+        let steps   = 1000;
+        let d       = ( LAMBDA_MAX - LAMBDA_MIN ) / steps as f64;
+        let mut xyz = Vec3f( 0.0, 0.0, 0.0 );
+
+        for i in 0..steps {
+            xyz += d * cie_xyz( LAMBDA_MIN + ( i as f64 + 0.5 ) * d );
+        }
+
+        xyz_to_rgb( &xyz )
+    })
+}
+
+// RGB contribution of the radiance values carried by a path with the given
+// hero wavelength.
+pub fn spectral_to_rgb( value: &Color, lambda: f64 ) -> Color {
+
+    let pdf         = 1.0 / ( LAMBDA_MAX - LAMBDA_MIN );
+    let wavelengths = hero_wavelengths( lambda );
+
+    let xyz = ( value.0 * cie_xyz( wavelengths[0] )
+              + value.1 * cie_xyz( wavelengths[1] )
+              + value.2 * cie_xyz( wavelengths[2] )) / ( 3.0 * pdf );
+
+    let rgb   = xyz_to_rgb( &xyz );
+    let white = spectral_white();
+
+    Color( rgb.0 / white.0, rgb.1 / white.1, rgb.2 / white.2 )
+}
+
+// RGB of a reflectance spectrum under a flat illuminant, so that a constant
+// reflectance keeps its value. Clamped to the gamut, as it scales radiance.
+pub fn reflectance_to_rgb( reflectance: impl Fn( f64 ) -> f64 ) -> Color {
+
+    let steps = 32;
+    let d     = ( LAMBDA_MAX - LAMBDA_MIN ) / steps as f64;
+
+    let mut xyz   = Vec3f( 0.0, 0.0, 0.0 );
+    let mut white = Vec3f( 0.0, 0.0, 0.0 );
+
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + ( i as f64 + 0.5 ) * d;
+        let cmf    = cie_xyz( lambda );
+
+        xyz   += reflectance( lambda ) * cmf;
+        white += cmf;
+    }
+
+    let rgb   = xyz_to_rgb( &xyz );
+    let white = xyz_to_rgb( &white );
+
+    Color( f64::max( rgb.0 / white.0, 0.0 ), f64::max( rgb.1 / white.1, 0.0 ), f64::max( rgb.2 / white.2, 0.0 ))
+}
+
+// Planck's law, spectral radiance in W / ( sr m^2 nm ) of a blackbody at the
+// given temperature in Kelvin, lambda in nm.
+pub fn blackbody( lambda: f64, temperature: f64 ) -> f64 {
+
+    const C: f64  = 299792458.0;
+    const H: f64  = 6.62607015e-34;
+    const KB: f64 = 1.380649e-23;
+
+    if temperature <= 0.0 {
+        return 0.0
+    }
+
+    let l = lambda * 1e-9;
+
+    2.0 * H * C * C / ( l.powi( 5 ) * ( f64::exp( H * C / ( l * KB * temperature )) - 1.0 )) * 1e-9
+}
+
+// Emission color of a blackbody. Normalized it has luminance 1 whatever the
+// temperature, otherwise a 6500 K body has luminance 1 and hotter ones are
+// brighter.
+pub fn blackbody_rgb( temperature: f64, normalize: bool ) -> Color {
+    blackbody_scale( temperature, normalize ) * emission_to_rgb( | lambda | blackbody( lambda, temperature ))
+}
+
+// Factor taking Planck's law to blackbody_rgb's brightness.
+fn blackbody_scale( temperature: f64, normalize: bool ) -> f64 {
+
+    let reference = luminance( &emission_to_rgb( | lambda | blackbody( lambda, if normalize { temperature } else { 6500.0 } )));
+
+    if reference > 0.0 { 1.0 / reference } else { 0.0 }
+}
+
+// Radiance of an emitter or a light, as seen by a ray with the given hero
+// wavelength like at_wavelength. RGB ones are upsampled as illuminants for
+// spectral rays, while blackbodies follow Planck's law there.
+#[derive( Clone, Copy )]
+pub enum Emission {
+    Rgb( Color ),
+    Blackbody { temperature: f64, scale: f64, rgb: Color },
+}
+
+impl Emission {
+
+    // See blackbody_rgb.
+    pub fn blackbody( temperature: f64, intensity: f64, normalize: bool ) -> Self {
+        Emission::Blackbody { temperature
+                            , scale: intensity * blackbody_scale( temperature, normalize )
+                            , rgb: intensity * blackbody_rgb( temperature, normalize ) }
+    }
+
+    pub fn at( &self, lambda: f64 ) -> Color {
+        match *self {
+            Emission::Rgb( color ) => illuminant_at_wavelength( &color, lambda ),
+            Emission::Blackbody { temperature, scale, .. } if lambda > 0.0 => {
+                at_hero_wavelengths( | l | scale * blackbody( l, temperature ), lambda )
+            }
+            Emission::Blackbody { rgb, .. } => rgb,
+        }
+    }
+}
+
+// RGB of an emission spectrum, relative to the equal energy one.
+fn emission_to_rgb( emission: impl Fn( f64 ) -> f64 ) -> Color {
+    let rgb = emission_to_rgb_unclamped( emission );
+    Color( f64::max( rgb.0, 0.0 ), f64::max( rgb.1, 0.0 ), f64::max( rgb.2, 0.0 ))
+}
+
+fn emission_to_rgb_unclamped( emission: impl Fn( f64 ) -> f64 ) -> Color {
+
+    let steps   = 340;
+    let d       = ( LAMBDA_MAX - LAMBDA_MIN ) / steps as f64;
+    let mut xyz = Vec3f( 0.0, 0.0, 0.0 );
+
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + ( i as f64 + 0.5 ) * d;
+        xyz += ( emission( lambda ) * d ) * cie_xyz( lambda );
+    }
+
+    let rgb   = xyz_to_rgb( &xyz );
+    let white = spectral_white();
+
+    Color( rgb.0 / white.0, rgb.1 / white.1, rgb.2 / white.2 )
+}
+
+// Value of an RGB quantity, like a metal's IOR, at a wavelength, reading the
+// channels as samples at 610, 550 and 465 nm.
+pub fn rgb_at_wavelength( color: &Color, lambda: f64 ) -> f64 {
+
+    let ( r, g, b ) = ( 610.0, 550.0, 465.0 );
+
+    if lambda >= r {
+        color.0
+    } else if lambda >= g {
+        color.1 + ( color.0 - color.1 ) * ( lambda - g ) / ( r - g )
+    } else if lambda >= b {
+        color.2 + ( color.1 - color.2 ) * ( lambda - b ) / ( g - b )
+    } else {
+        color.2
+    }
+}
+
+// Smits, "An RGB to Spectrum Conversion for Reflectances", 10 bins over the
+// LAMBDA_MIN to LAMBDA_MAX range.
+const SMITS_WHITE: [ f64; 10 ]   = [ 1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000 ];
+const SMITS_CYAN: [ f64; 10 ]    = [ 0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000 ];
+const SMITS_MAGENTA: [ f64; 10 ] = [ 1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959 ];
+const SMITS_YELLOW: [ f64; 10 ]  = [ 0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840 ];
+const SMITS_RED: [ f64; 10 ]     = [ 0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149 ];
+const SMITS_GREEN: [ f64; 10 ]   = [ 0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025 ];
+const SMITS_BLUE: [ f64; 10 ]    = [ 1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496 ];
+
+pub fn rgb_to_spectrum( color: &Color, lambda: f64 ) -> f64 {
+
+    let bin = ((( lambda - LAMBDA_MIN ) / ( LAMBDA_MAX - LAMBDA_MIN ) * 10.0 ) as isize ).clamp( 0, 9 ) as usize;
+
+    let ( r, g, b ) = ( f64::max( color.0, 0.0 ), f64::max( color.1, 0.0 ), f64::max( color.2, 0.0 ));
+
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin]
+      + if g <= b { ( g - r ) * SMITS_CYAN[bin] + ( b - g ) * SMITS_BLUE[bin] }
+        else      { ( b - r ) * SMITS_CYAN[bin] + ( g - b ) * SMITS_GREEN[bin] }
+    } else if g <= r && g <= b {
+        g * SMITS_WHITE[bin]
+      + if r <= b { ( r - g ) * SMITS_MAGENTA[bin] + ( b - r ) * SMITS_BLUE[bin] }
+        else      { ( b - g ) * SMITS_MAGENTA[bin] + ( r - b ) * SMITS_RED[bin] }
+    } else {
+        b * SMITS_WHITE[bin]
+      + if r <= g { ( r - b ) * SMITS_YELLOW[bin] + ( g - r ) * SMITS_GREEN[bin] }
+        else      { ( g - b ) * SMITS_YELLOW[bin] + ( r - g ) * SMITS_RED[bin] }
+    }
+}
+
+// The reflectance as seen by a ray with the given hero wavelength: unchanged
+// for RGB rays (wavelength 0), its spectrum's values at the path's wavelengths
+// for spectral ones.
+pub fn at_wavelength( color: &Color, lambda: f64 ) -> Color {
+
+    if lambda <= 0.0 {
+        return *color
+    }
+
+    let wavelengths = hero_wavelengths( lambda );
+
+    Color( rgb_to_spectrum( color, wavelengths[0] )
+         , rgb_to_spectrum( color, wavelengths[1] )
+         , rgb_to_spectrum( color, wavelengths[2] ))
+}
+
+// Red, green and blue bands with smooth edges at 590 and 490 nm, adding up to
+// one at every wavelength.
+fn illuminant_band( band: usize, lambda: f64 ) -> f64 {
+
+    let step = | edge: f64 | 1.0 / ( 1.0 + f64::exp( -( lambda - edge ) / 10.0 ));
+
+    match band {
+        0 => step( 590.0 ),
+        1 => step( 490.0 ) - step( 590.0 ),
+        _ => 1.0 - step( 490.0 ),
+    }
+}
+
+// Inverse of the matrix whose columns are the bands' RGB.
+fn illuminant_basis() -> &'static [ Vec3f; 3 ] {
+
+    static BASIS: OnceLock<[ Vec3f; 3 ]> = OnceLock::new();
+
+    BASIS.get_or_init( || {
+        let c = [ emission_to_rgb_unclamped( | lambda | illuminant_band( 0, lambda ))
+                , emission_to_rgb_unclamped( | lambda | illuminant_band( 1, lambda ))
+                , emission_to_rgb_unclamped( | lambda | illuminant_band( 2, lambda )) ];
+
+        // Rows of the inverse are the cross products of the columns over the
+        // determinant.
+        let det = dot( c[0], cross( c[1], c[2] ));
+
+        [ cross( c[1], c[2] ) / det, cross( c[2], c[0] ) / det, cross( c[0], c[1] ) / det ]
+    })
+}
+
+// Emission spectrum of an RGB radiance, a mix of the three bands that gives
+// back exactly that RGB. Unlike reflectances it's unbounded, and white is the
+// flat spectrum. Very saturated colors can need slightly negative bands.
+pub fn illuminant_to_spectrum( color: &Color, lambda: f64 ) -> f64 {
+
+    let basis = illuminant_basis();
+
+    ( 0..3 ).map( |band| dot( basis[band], *color ) * illuminant_band( band, lambda )).sum()
+}
+
+// at_wavelength for emitted radiance, lights and backgrounds.
+pub fn illuminant_at_wavelength( color: &Color, lambda: f64 ) -> Color {
+
+    if lambda <= 0.0 {
+        return *color
+    }
+
+    let wavelengths = hero_wavelengths( lambda );
+
+    Color( illuminant_to_spectrum( color, wavelengths[0] )
+         , illuminant_to_spectrum( color, wavelengths[1] )
+         , illuminant_to_spectrum( color, wavelengths[2] ))
+}
+
+// Index of refraction, possibly depending on the wavelength in nm.
+#[derive( Clone, Copy )]
+pub enum Ior {
+    Constant( f64 ),
+    // n = a + b / lambda^2, lambda in micrometers.
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum b_i lambda^2 / ( lambda^2 - c_i ), lambda in micrometers.
+    Sellmeier { b: [ f64; 3 ], c: [ f64; 3 ] },
+}
+
+impl Ior {
+
+    // Schott N-BK7 crown glass.
+    pub const BK7: Ior = Ior::Sellmeier { b: [ 1.03961212, 0.231792344, 1.01046945 ]
+                                        , c: [ 0.00600069867, 0.0200179144, 103.560653 ] };
+
+    pub const DIAMOND: Ior = Ior::Sellmeier { b: [ 4.3356, 0.3306, 0.0 ]
+                                            , c: [ 0.1060 * 0.1060, 0.1750 * 0.1750, 0.0 ] };
+
+    // lambda 0 means an RGB ray, which uses LAMBDA_REFERENCE.
+    pub fn at( &self, lambda: f64 ) -> f64 {
+
+        let lambda = if lambda > 0.0 { lambda } else { LAMBDA_REFERENCE };
+        let l2     = ( lambda / 1000.0 ) * ( lambda / 1000.0 );
+
+        match self {
+            Ior::Constant( n )      => *n,
+            Ior::Cauchy { a, b }    => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let n2 = 1.0 + ( 0..3 ).map( |i| b[i] * l2 / ( l2 - c[i] )).sum::<f64>();
+                f64::sqrt( n2 )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn bk7_at_the_helium_d_line() {
+        assert!(( Ior::BK7.at( 587.6 ) - 1.5168 ).abs() < 1e-4 );
+    }
+
+    #[test]
+    fn bk7_disperses_blue_more_than_red() {
+        assert!( Ior::BK7.at( 450.0 ) > Ior::BK7.at( 650.0 ));
+    }
+
+    #[test]
+    fn rgb_rays_use_the_reference_wavelength() {
+        assert_eq!( Ior::BK7.at( 0.0 ), Ior::BK7.at( LAMBDA_REFERENCE ));
+    }
+}
@@ -0,0 +1,85 @@
+use super::color::Color;
+use super::image::Image;
+use super::math::interval::Interval;
+use super::math::vec3f::*;
+
+pub trait Texture: Send + Sync {
+    fn value( &self, u: f64, v: f64, p: &Point3 ) -> Color;
+}
+
+pub struct Solid_color {
+    albedo: Color,
+}
+
+impl Solid_color {
+
+    pub fn new( albedo: &Color ) -> Self {
+        Self { albedo: *albedo }
+    }
+
+    // For textures standing in for a single number, like roughness.
+    pub fn scalar( value: f64 ) -> Self {
+        Self::new( &Color( value, value, value ))
+    }
+}
+
+impl Texture for Solid_color {
+    fn value( &self, u: f64, v: f64, p: &Point3 ) -> Color {
+        self.albedo
+    }
+}
+
+pub struct Checker_texture {
+    inv_scale: f64,
+    even: Color,
+    odd: Color,
+}
+
+impl Checker_texture {
+    pub fn new( scale: f64, even: &Color, odd: &Color ) -> Self {
+        Self { inv_scale: 1.0 / scale, even: *even, odd: *odd }
+    }
+}
+
+impl Texture for Checker_texture {
+
+    fn value( &self, u: f64, v: f64, p: &Point3 ) -> Color {
+
+        let x = f64::floor( self.inv_scale * p.x() ) as i64;
+        let y = f64::floor( self.inv_scale * p.y() ) as i64;
+        let z = f64::floor( self.inv_scale * p.z() ) as i64;
+
+        if ( x + y + z ) % 2 == 0 { self.even } else { self.odd }
+    }
+}
+
+pub struct Image_texture {
+    image: Image,
+}
+
+impl Image_texture {
+    pub fn new( image: Image ) -> Self {
+        Self { image }
+    }
+}
+
+impl Texture for Image_texture {
+
+    fn value( &self, u: f64, v: f64, p: &Point3 ) -> Color {
+
+        let u = Interval::new( 0.0, 1.0 ).clamp( u );
+        let v = 1.0 - Interval::new( 0.0, 1.0 ).clamp( v );
+
+        // Bilinear, so that bump maps get continuous slopes.
+        let x = f64::max( u * self.image.width() as f64 - 0.5, 0.0 );
+        let y = f64::max( v * self.image.height() as f64 - 0.5, 0.0 );
+
+        let ( x0, y0 ) = ( x as usize, y as usize );
+        let ( fx, fy ) = ( x - x0 as f64, y - y0 as f64 );
+
+        let top    = ( 1.0 - fx ) * self.image.pixel( x0, y0     ) + fx * self.image.pixel( x0 + 1, y0     );
+        let bottom = ( 1.0 - fx ) * self.image.pixel( x0, y0 + 1 ) + fx * self.image.pixel( x0 + 1, y0 + 1 );
+
+        ( 1.0 - fy ) * top + fy * bottom
+    }
+}
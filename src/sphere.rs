@@ -9,17 +9,69 @@ use crate::raytracer::math::vec3f::*;
 use crate::raytracer::ray::*;
 use crate::raytracer::hittable::*;
 use crate::raytracer::material::*;
+use crate::raytracer::math::PI;
 
 pub struct Sphere {
     center: Point3,
     radius: f64,
     mat: Arc<dyn Material + Send + Sync>,
+    cull_back: bool,
 }
 
 impl Sphere {
 
     pub fn new( center: Point3, radius: f64, mat: Arc<dyn Material + Send + Sync> ) -> Sphere {
-        Sphere { center, radius, mat }
+        Sphere { center, radius, mat, cull_back: false }
+    }
+
+    // Only hit from outside, invisible from within.
+    pub fn cull_back_faces( self ) -> Sphere {
+        Sphere { cull_back: true, ..self }
+    }
+
+    // p is a point on the unit sphere. u goes around the y axis starting at
+    // x = -1, v from the bottom pole to the top one.
+    fn get_sphere_uv( p: &Point3 ) -> ( f64, f64 ) {
+
+        let theta = f64::acos( -p.y() );
+        let phi   = f64::atan2( -p.z(), p.x() ) + PI;
+
+        ( phi / ( 2.0 * PI ), theta / PI )
+    }
+
+    fn record_at( &self, r: &Ray, root: f64 ) -> Hit_record {
+
+        let mut rec = Hit_record::new();
+
+        rec.t = root;
+        rec.p = r.at( rec.t );
+
+        let outward_normal = ( rec.p - self.center ) / self.radius;
+        rec.set_face_normal( r, &outward_normal );
+
+        ( rec.u, rec.v )       = Sphere::get_sphere_uv( &outward_normal );
+        ( rec.dpdu, rec.dpdv ) = self.get_sphere_tangents( &outward_normal );
+
+        rec.mat = self.mat.clone();
+
+        rec
+    }
+
+    // Derivatives of the hit point along u and v, from the mapping above.
+    fn get_sphere_tangents( &self, p: &Point3 ) -> ( Vec3f, Vec3f ) {
+
+        let rho  = f64::sqrt( p.x() * p.x() + p.z() * p.z() );
+        let dpdu = 2.0 * PI * self.radius * Vec3f( p.z(), 0.0, -p.x() );
+
+        // Any tangent will do at the poles.
+        if rho < 1e-9 {
+            let dpdv = PI * self.radius * Vec3f( 1.0, 0.0, 0.0 );
+            return ( cross( dpdv, *p ), dpdv )
+        }
+
+        let dpdv = PI * self.radius * Vec3f( -p.x() * p.y() / rho, rho, -p.y() * p.z() / rho );
+
+        ( dpdu, dpdv )
     }
 }
 
@@ -38,32 +90,26 @@ impl Hittable for Sphere {
         }
 
         let sqrtd = f64::sqrt( discriminant );
-        
-        let root = {
-            let mut root = ( h - sqrtd ) / a;
+
+        // The far root is still a candidate when the near one is cut out.
+        for root in [ ( h - sqrtd ) / a, ( h + sqrtd ) / a ] {
+
             if !ray_t.surrounds( root ) {
-                root = ( h + sqrtd ) / a;
-                if !ray_t.surrounds( root ) {
-                    return ( false, Hit_record::new() )
-                } else {
-                    root
-                }
-            } else {
-                root
+                continue;
             }
-        };
 
-        let mut rec = Hit_record::new();
+            let rec = self.record_at( r, root );
 
-        rec.t = root;
-        rec.p = r.at( rec.t );
+            if self.cull_back && !rec.front_face {
+                continue;
+            }
 
-        let outward_normal = ( rec.p - self.center ) / self.radius;
-        rec.set_face_normal( r, &outward_normal );
-        
-        rec.mat = self.mat.clone();
+            if !rec.mat.is_cutout( &rec ) {
+                return ( true, rec )
+            }
+        }
 
-        ( true, rec )
+        ( false, Hit_record::new() )
     }
 
     fn hittable( &self ) { (); }